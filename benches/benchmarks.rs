@@ -1,11 +1,11 @@
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
 use merge_sort::{
     gpu_sort::merge_sort_gpu,
     multicore_sort::{
-        merge_sort_parallel, merge_sort_parallel_limit, merge_sort_threadpool,
-        merge_sort_threadpool_chunks,
+        merge_sort_hybrid, merge_sort_parallel, merge_sort_parallel_limit, merge_sort_threadpool,
+        merge_sort_threadpool_chunks, sample_sort,
     },
-    single_core_sort::merge_sort,
+    single_core_sort::{merge_sort, merge_sort_in_place, merge_sort_natural, timsort},
 };
 use std::{hint::black_box, time::Duration};
 const SIZE: usize = 1_000_000;
@@ -21,6 +21,55 @@ pub fn sequential_sort_benchmark(c: &mut Criterion) {
     });
 }
 
+pub fn in_place_sequential_sort_benchmark(c: &mut Criterion) {
+    let size = SIZE;
+    let mut vec: Vec<i32> = Vec::with_capacity(size);
+    for _ in 1..size {
+        vec.push(rand::random());
+    }
+    c.bench_function("in place sequential sort {size}", |b| {
+        b.iter_batched_ref(
+            || vec.clone(),
+            |v| merge_sort_in_place(black_box(v)),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+pub fn structured_inputs_benchmark(c: &mut Criterion) {
+    let size = SIZE;
+    let mut random: Vec<i32> = Vec::with_capacity(size);
+    for _ in 1..size {
+        random.push(rand::random());
+    }
+    let sorted: Vec<i32> = (0..size as i32).collect();
+    let reversed: Vec<i32> = sorted.iter().rev().copied().collect();
+    let sawtooth: Vec<i32> = (0..size as i32).map(|i| i % 1000).collect();
+    // Sorted with a few random values appended, like a log re-sorted after updates
+    let mut appended = sorted.clone();
+    appended.extend_from_slice(&random[..size / 100]);
+
+    let mut group = c.benchmark_group("structured inputs");
+    for (name, vec) in [
+        ("random", &random),
+        ("sorted", &sorted),
+        ("reversed", &reversed),
+        ("sawtooth", &sawtooth),
+        ("appended", &appended),
+    ] {
+        group.bench_function(format!("sequential sort {name}"), |b| {
+            b.iter(|| merge_sort(black_box(vec)))
+        });
+        group.bench_function(format!("natural sort {name}"), |b| {
+            b.iter(|| merge_sort_natural(black_box(vec)))
+        });
+        group.bench_function(format!("timsort {name}"), |b| {
+            b.iter(|| timsort(black_box(vec)))
+        });
+    }
+    group.finish();
+}
+
 pub fn parallel_sort_benchmark(c: &mut Criterion) {
     let size = SIZE;
     let mut vec: Vec<i32> = Vec::with_capacity(size);
@@ -65,6 +114,75 @@ pub fn threadpool_chunks_sort_benchmark(c: &mut Criterion) {
     });
 }
 
+// Speedup of the parallel sorts over the sequential one as threads are added
+pub fn parallel_speedup_benchmark(c: &mut Criterion) {
+    let size = SIZE;
+    let mut vec: Vec<i32> = Vec::with_capacity(size);
+    for _ in 1..size {
+        vec.push(rand::random());
+    }
+    let mut group = c.benchmark_group("parallel speedup");
+    group.bench_function("sequential sort", |b| {
+        b.iter(|| merge_sort(black_box(&vec)))
+    });
+    for threads in [1, 2, 4, 8] {
+        group.bench_function(format!("parallel limit sort {threads} threads"), |b| {
+            b.iter(|| merge_sort_parallel_limit(black_box(&vec), threads))
+        });
+        group.bench_function(
+            format!("threadpool sort in chunks {threads} threads"),
+            |b| b.iter(|| merge_sort_threadpool_chunks(black_box(&vec), threads)),
+        );
+        group.bench_function(format!("hybrid sort {threads} threads"), |b| {
+            b.iter(|| merge_sort_hybrid(black_box(&vec), threads))
+        });
+    }
+    group.finish();
+}
+
+// Sample sort against the merge-based parallel sorts
+pub fn sample_sort_benchmark(c: &mut Criterion) {
+    let size = SIZE;
+    let threads = 8;
+    let mut vec: Vec<i32> = Vec::with_capacity(size);
+    for _ in 1..size {
+        vec.push(rand::random());
+    }
+    let mut group = c.benchmark_group("parallel backends");
+    group.bench_function("sample sort", |b| {
+        b.iter(|| sample_sort(black_box(&vec), threads))
+    });
+    group.bench_function("parallel limit sort", |b| {
+        b.iter(|| merge_sort_parallel_limit(black_box(&vec), threads))
+    });
+    group.bench_function("threadpool sort", |b| {
+        b.iter(|| merge_sort_threadpool(black_box(&vec), threads))
+    });
+    group.bench_function("threadpool sort in chunks", |b| {
+        b.iter(|| merge_sort_threadpool_chunks(black_box(&vec), threads))
+    });
+    group.finish();
+}
+
+// The plain parallel sort spawns one thread per block of 16 values, which 1M values
+// can't survive, so it is compared with the sample sort on a smaller input
+pub fn small_sample_sort_benchmark(c: &mut Criterion) {
+    let size = SIZE / 100;
+    let threads = 8;
+    let mut vec: Vec<i32> = Vec::with_capacity(size);
+    for _ in 1..size {
+        vec.push(rand::random());
+    }
+    let mut group = c.benchmark_group("parallel backends 10000 values");
+    group.bench_function("sample sort", |b| {
+        b.iter(|| sample_sort(black_box(&vec), threads))
+    });
+    group.bench_function("parallel sort", |b| {
+        b.iter(|| merge_sort_parallel(black_box(&vec)))
+    });
+    group.finish();
+}
+
 pub fn gpu_sort_benchmark(c: &mut Criterion) {
     let size = SIZE;
     let mut vec: Vec<i32> = Vec::with_capacity(size);
@@ -81,9 +199,14 @@ criterion_group!(
     config = Criterion::default().measurement_time(Duration::from_secs(20)).sample_size(50);
     targets =
         sequential_sort_benchmark,
+        in_place_sequential_sort_benchmark,
+        structured_inputs_benchmark,
         threadpool_sort_benchmark,
         parallel_limit_sort_benchmark,
         threadpool_chunks_sort_benchmark,
+        parallel_speedup_benchmark,
+        sample_sort_benchmark,
+        small_sample_sort_benchmark,
         gpu_sort_benchmark,
 );
 criterion_main!(benches);
@@ -1,7 +1,8 @@
 use flume::bounded;
-use pollster::FutureExt; // to block on the future
 
 use std::error::Error;
+
+use crate::single_core_sort::{FloatOrder, NanPolicy, PassObserver, SignedZero, SortStats, StatsRecorder};
 use wgpu::{
     self,
     util::{BufferInitDescriptor, DeviceExt},
@@ -10,12 +11,61 @@ use wgpu::{
 /// Strongly inspired from https://github.com/sotrh/learn-wgpu/blob/master/code/compute/src/introduction.rs
 /// with the goal of learning the basics of gpu compute with wgpu
 pub async fn merge_sort_gpu(input: Vec<i32>) -> Result<Vec<i32>, Box<dyn Error>> {
+    run_merge_passes(
+        wgpu::include_wgsl!("merge_sort_shader.wgsl"),
+        &[bytemuck::cast_slice(&input)],
+        input.len(),
+        &mut (),
+    )
+    .await
+}
+
+/// Same as `merge_sort_gpu`, along with what the sort did. Each pass is waited for
+/// to time it, which makes the sort slower.
+pub async fn merge_sort_gpu_with_stats(input: Vec<i32>) -> Result<(Vec<i32>, SortStats), Box<dyn Error>> {
+    let mut recorder = StatsRecorder::default();
+    let sorted = run_merge_passes(
+        wgpu::include_wgsl!("merge_sort_shader.wgsl"),
+        &[bytemuck::cast_slice(&input)],
+        input.len(),
+        &mut recorder,
+    )
+    .await?;
+    Ok((sorted, recorder.stats))
+}
+
+/// Indices that would sort the input, equal values keep their original order.
+pub async fn merge_argsort_gpu(input: Vec<i32>) -> Result<Vec<u32>, Box<dyn Error>> {
+    let indices: Vec<u32> = (0..u32::try_from(input.len())?).collect();
+    run_merge_passes(
+        wgpu::include_wgsl!("merge_argsort_shader.wgsl"),
+        &[bytemuck::cast_slice(&input), bytemuck::cast_slice(&indices)],
+        input.len(),
+        &mut (),
+    )
+    .await
+}
+
+// Runs the merge passes of the shader on arrays of `length` elements.
+// The shader reads each array from the binding 2 * i, writes it to the binding 2 * i + 1,
+// and finds the bin size in the last binding. Returns the last array once sorted.
+// Each pass is submitted on its own, so that the observer can wait for it.
+async fn run_merge_passes<R: bytemuck::Pod, O: PassObserver>(
+    shader: wgpu::ShaderModuleDescriptor<'_>,
+    arrays: &[&[u8]],
+    length: usize,
+    observer: &mut O,
+) -> Result<Vec<R>, Box<dyn Error>> {
+    // Zero-size buffers cannot be mapped to read the result back
+    if length == 0 {
+        return Ok(Vec::new());
+    }
     //Strongly
     let instance = wgpu::Instance::new(&Default::default());
     let adapter = instance.request_adapter(&Default::default()).await.unwrap();
     let (device, queue) = adapter.request_device(&Default::default()).await.unwrap();
 
-    let shader = device.create_shader_module(wgpu::include_wgsl!("merge_sort_shader.wgsl"));
+    let shader = device.create_shader_module(shader);
 
     let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
         label: Some("merge sort compute pipeline"),
@@ -26,85 +76,104 @@ pub async fn merge_sort_gpu(input: Vec<i32>) -> Result<Vec<i32>, Box<dyn Error>>
         cache: Default::default(),
     });
 
-    let input_buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: Some("input"),
-        contents: bytemuck::cast_slice(&input),
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-    });
+    // Input and output buffers of each array
+    let buffers: Vec<(wgpu::Buffer, wgpu::Buffer)> = arrays.iter().map(|contents| {
+        let input_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("input"),
+            contents,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
 
-    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("output"),
-        size: input_buffer.size(),
-        usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
-        mapped_at_creation: false,
-    });
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size: input_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        (input_buffer, output_buffer)
+    }).collect();
+    let (_, output_buffer) = buffers.last().unwrap();
 
     let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("temp"),
-        size: input_buffer.size(),
+        size: output_buffer.size(),
         usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
         mapped_at_creation: false,
     });
+    observer.allocated(2 * buffers.len() + 1);
 
 
-    let mut encoder = device.create_command_encoder(&Default::default());
-
     {
 
         let mut bin_size = 1;
 
 
 
-        while bin_size < input.len() {
+        while bin_size < length {
+            observer.start_pass();
+            let mut encoder = device.create_command_encoder(&Default::default());
             // Calculate the number of passes for 1 merge sort step on the full data
             let num_items_per_workgroup = 64 * bin_size * 2; // 64 threads, 2 bins per thread
-            let num_dispatches = (input.len() / num_items_per_workgroup) as u32
-                           + (input.len() % num_items_per_workgroup > 0) as u32;
-            println!("num dispatches: {num_dispatches}");
+            let num_dispatches = (length / num_items_per_workgroup) as u32
+                           + !length.is_multiple_of(num_items_per_workgroup) as u32;
 
             // Reinjecting the partially sorted data to the input buffer
             if bin_size > 1 {
-                encoder.copy_buffer_to_buffer(&output_buffer, 0, &input_buffer, 0, output_buffer.size());
+                for (input_buffer, output_buffer) in &buffers {
+                    encoder.copy_buffer_to_buffer(output_buffer, 0, input_buffer, 0, output_buffer.size());
+                }
             }
 
             // Shared bin size buffer between all GPU threads
             let bin_size_buffer = device.create_buffer_init(&BufferInitDescriptor {
                 label: Some("Size of a bin at this step"),
-                contents: &[bin_size.try_into().unwrap()],
+                contents: bytemuck::bytes_of(&u32::try_from(bin_size)?),
                 usage: wgpu::BufferUsages::STORAGE,
             });
+            observer.allocated(1);
 
             // Initialize the pipeline at ech merge sort step
             // Necessary because we change the bin size buffer each time
             // Seems inefficient, is there a better way ?
+            let mut entries: Vec<wgpu::BindGroupEntry> = Vec::new();
+            for (input_buffer, output_buffer) in &buffers {
+                entries.push(wgpu::BindGroupEntry {
+                    binding: entries.len() as u32,
+                    resource: input_buffer.as_entire_binding(),
+                });
+                entries.push(wgpu::BindGroupEntry {
+                    binding: entries.len() as u32,
+                    resource: output_buffer.as_entire_binding(),
+                });
+            }
+            entries.push(wgpu::BindGroupEntry {
+                binding: entries.len() as u32,
+                resource: bin_size_buffer.as_entire_binding(),
+            });
             let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: None,
                 layout: &pipeline.get_bind_group_layout(0),
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: input_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: output_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: bin_size_buffer.as_entire_binding(),
-                    },
-                ],
+                entries: &entries,
             });
             let mut pass = encoder.begin_compute_pass(&Default::default());
             pass.set_pipeline(&pipeline);
             pass.set_bind_group(0, &bind_group, &[]);
             pass.dispatch_workgroups(num_dispatches, 1, 1);
+            drop(pass);
+            queue.submit([encoder.finish()]);
+            observer.dispatched(num_dispatches as usize);
+            observer.moved(length);
+            if O::TIMES_PASSES {
+                device.poll(wgpu::PollType::wait_indefinitely())?;
+            }
+            observer.finish_pass();
 
             bin_size *= 2;
         }
     }
 
-    encoder.copy_buffer_to_buffer(&output_buffer, 0, &temp_buffer, 0, output_buffer.size());
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(output_buffer, 0, &temp_buffer, 0, output_buffer.size());
 
     queue.submit([encoder.finish()]);
 
@@ -123,9 +192,56 @@ pub async fn merge_sort_gpu(input: Vec<i32>) -> Result<Vec<i32>, Box<dyn Error>>
     Ok(Vec::from(bytemuck::cast_slice(&output_data)))
 }
 
+/// Sorts floats in the total order given by `order`, see `single_core_sort::FloatOrder`.
+/// The GPU sorts integer keys ordered like the floats, NaN values and zeros
+/// treated as equal are put back in their original order on the CPU.
+pub async fn merge_sort_gpu_floats(
+    input: Vec<f32>,
+    order: FloatOrder,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    order.check(&input)?;
+    let (nans, numbers): (Vec<f32>, Vec<f32>) = input.into_iter().partition(|v| v.is_nan());
+    let keys = numbers.iter().map(|v| float_to_key(*v)).collect();
+    let mut sorted: Vec<f32> = merge_sort_gpu(keys)
+        .await?
+        .into_iter()
+        .map(key_to_float)
+        .collect();
+
+    if order.signed_zero == SignedZero::Equal {
+        // The zeros are contiguous once sorted, -0.0 before +0.0
+        let zeros_start = sorted.partition_point(|v| *v < 0.0);
+        for (sorted, zero) in sorted[zeros_start..]
+            .iter_mut()
+            .zip(numbers.iter().filter(|v| **v == 0.0))
+        {
+            *sorted = *zero;
+        }
+    }
+    if order.nan == NanPolicy::First {
+        sorted.splice(0..0, nans);
+    } else {
+        sorted.extend(nans);
+    }
+    Ok(sorted)
+}
+
+// Maps the floats to integers in the same order as `f32::total_cmp`:
+// the bits of negative values are reversed, except for the sign
+fn float_to_key(value: f32) -> i32 {
+    let bits = value.to_bits() as i32;
+    bits ^ (((bits >> 31) as u32) >> 1) as i32
+}
+
+fn key_to_float(key: i32) -> f32 {
+    f32::from_bits((key ^ (((key >> 31) as u32) >> 1) as i32) as u32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::scrambled;
+    use pollster::FutureExt; // to block on the future
 
     #[test]
     fn sort_small_vec() {
@@ -135,4 +251,112 @@ mod tests {
             vec![1, 3, 15, 24, 53]
         )
     }
+
+    #[test]
+    fn sort_with_stats() {
+        let test_vec: Vec<i32> = scrambled(1000, 1009).collect();
+        let (sorted, stats) = merge_sort_gpu_with_stats(test_vec.clone()).block_on().unwrap();
+        let mut expected = test_vec;
+        expected.sort();
+        assert_eq!(sorted, expected);
+        assert_eq!(stats.passes, 10);
+        assert_eq!(stats.pass_times.len(), 10);
+        // 128 values per workgroup on the first pass
+        assert_eq!(stats.dispatches, 8 + 4 + 2 + 1 + 6);
+        // Input, output and read back buffers, then a bin size buffer per pass
+        assert_eq!(stats.allocations, 3 + 10);
+    }
+
+    #[test]
+    fn sort_floats() {
+        let test_vec = vec![
+            0.0,
+            f32::NAN,
+            -0.0,
+            2.5,
+            f32::NEG_INFINITY,
+            -1.0,
+            0.0,
+            -f32::NAN,
+        ];
+        for nan in [NanPolicy::First, NanPolicy::Last] {
+            for signed_zero in [SignedZero::Equal, SignedZero::NegativeFirst] {
+                let order = FloatOrder { nan, signed_zero };
+                let expected = crate::single_core_sort::merge_sort_floats(&test_vec, order);
+                let sorted = merge_sort_gpu_floats(test_vec.clone(), order).block_on();
+                assert_eq!(
+                    sorted
+                        .unwrap()
+                        .iter()
+                        .map(|v| v.to_bits())
+                        .collect::<Vec<_>>(),
+                    expected
+                        .unwrap()
+                        .iter()
+                        .map(|v| v.to_bits())
+                        .collect::<Vec<_>>()
+                );
+            }
+        }
+        let order = FloatOrder {
+            nan: NanPolicy::Error,
+            signed_zero: SignedZero::Equal,
+        };
+        assert!(merge_sort_gpu_floats(test_vec, order).block_on().is_err());
+    }
+
+    #[test]
+    fn sort_floats_without_numbers() {
+        for nan in [NanPolicy::First, NanPolicy::Last] {
+            let order = FloatOrder {
+                nan,
+                signed_zero: SignedZero::Equal,
+            };
+            let nans = vec![f32::NAN, -f32::NAN];
+            let sorted = merge_sort_gpu_floats(nans.clone(), order).block_on().unwrap();
+            // The NaN values keep their original order
+            assert_eq!(
+                sorted.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                nans.iter().map(|v| v.to_bits()).collect::<Vec<_>>()
+            );
+            assert!(merge_sort_gpu_floats(vec![], order).block_on().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn float_keys_follow_total_order() {
+        let values = [
+            f32::NEG_INFINITY,
+            -1.5,
+            -f32::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            1e-40,
+            3.0,
+            f32::INFINITY,
+        ];
+        for pair in values.windows(2) {
+            assert!(float_to_key(pair[0]) < float_to_key(pair[1]));
+        }
+        for value in values {
+            assert_eq!(key_to_float(float_to_key(value)).to_bits(), value.to_bits());
+        }
+    }
+
+    #[test]
+    fn argsort_small_vec() {
+        let test_vec = vec![15, 53, 1, 24, 15, 3, -7];
+        assert_eq!(
+            merge_argsort_gpu(test_vec).block_on().unwrap(),
+            vec![6, 2, 5, 0, 4, 3, 1]
+        );
+        // Spans several workgroups
+        let test_vec: Vec<i32> = scrambled(1000, 97).map(|v: i32| v - 40).collect();
+        let expected: Vec<u32> = crate::single_core_sort::merge_argsort(&test_vec)
+            .into_iter()
+            .map(|id| id as u32)
+            .collect();
+        assert_eq!(merge_argsort_gpu(test_vec).block_on().unwrap(), expected);
+        assert!(merge_argsort_gpu(vec![]).block_on().unwrap().is_empty());
+    }
 }
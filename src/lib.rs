@@ -1,3 +1,12 @@
-pub mod single_core_sort;
-pub mod multicore_sort;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "gpu")]
 pub mod gpu_sort;
+#[cfg(feature = "std")]
+pub mod multicore_sort;
+pub mod single_core_sort;
+#[cfg(test)]
+mod test_utils;
@@ -1,271 +1,1132 @@
-use std::sync::{Arc, Mutex, RwLock, mpsc};
-use std::thread;
+//! Parallel merge sorts.
+//!
+//! Each pass splits the values and the buffer into disjoint chunks, one per task,
+//! so that the tasks merge their bins without sharing any element.
+//!
+//! If a comparison panics in a worker thread, the panic is propagated to the caller
+//! once every worker of the current pass has stopped, and each element is dropped
+//! exactly once. The `_in_place` sorts leave the caller's slice holding a permutation
+//! of its input, in an unspecified order.
 
+use std::cmp::Ordering;
+use std::mem::MaybeUninit;
+use std::panic;
+use std::sync::atomic::{self, AtomicU64};
+use std::thread::{self, ScopedJoinHandle};
+
+mod sample_sort;
 mod threadpool;
+pub use crate::multicore_sort::sample_sort::{
+    SampleSortConfig, sample_sort, sample_sort_by, sample_sort_by_key, sample_sort_in_place,
+    sample_sort_in_place_by, sample_sort_in_place_by_key, sample_sort_vec, sample_sort_with_config,
+};
 use crate::multicore_sort::threadpool::ThreadPool;
+use crate::single_core_sort::{
+    Column, FloatOrder, KeyColumn, NanError, PassObserver, Semantics, SetOp, SortConfig, SortFloat,
+    SortStats, SortVecPair, StatsRecorder, columns_length, compare_rows, merge_bins, merge_many_by,
+    merge_sort_impl, merge_sort_in_place_by, set_op_impl, timsort_impl,
+};
 // Trait aliasing for readibility
 // https://stackoverflow.com/questions/26070559/is-there-any-way-to-create-a-type-alias-for-multiple-traits
-pub trait SortTraits: Clone + PartialOrd + Send + Sync + 'static {}
-impl<T: Clone + PartialOrd + Send + Sync + 'static> SortTraits for T {}
-
-struct SortVecPair<T: SortTraits> {
-    bin_size: RwLock<usize>,
-    length: usize,
-    values: Vec<Mutex<T>>,
-    buffer: Vec<Mutex<T>>,
-}
-struct BinsPositions {
-    start: usize,
-    mid: usize,
-    end: usize,
-}
-struct SortThreadData<T: SortTraits> {
-    vec_pair: Arc<SortVecPair<T>>,
-    bins_positions: BinsPositions,
-}
-impl<T: SortTraits> SortVecPair<T> {
-    fn new(unsorted_vec: &[T]) -> SortVecPair<T> {
-        let mut buffer: Vec<Mutex<T>> = Vec::with_capacity(unsorted_vec.len());
-        let mut values: Vec<Mutex<T>> = Vec::with_capacity(unsorted_vec.len());
-        for val in unsorted_vec {
-            buffer.push(Mutex::new(val.clone()));
-            values.push(Mutex::new(val.clone()));
-        }
-        SortVecPair {
-            bin_size: RwLock::new(1),
-            length: unsorted_vec.len(),
-            values: values,
-            buffer: buffer,
+// Elements are moved rather than cloned, `Clone` is only needed to sort a copy of a slice
+pub trait SortTraits: PartialOrd + Send + Sync {}
+impl<T: PartialOrd + Send + Sync> SortTraits for T {}
+// Bounds of the elements sorted with a comparator or a key
+pub trait SortByTraits: Send + Sync {}
+impl<T: Send + Sync> SortByTraits for T {}
+// Bounds of the comparators shared between the threads
+pub trait LessFn<T>: Fn(&T, &T) -> bool + Sync {}
+impl<T, F: Fn(&T, &T) -> bool + Sync> LessFn<T> for F {}
+
+// How the tasks of each pass are run: on threads spawned for the pass or on a pool,
+// either one task per block or pair of bins, or one contiguous range per thread
+#[derive(Clone, Copy)]
+struct Workers<'a> {
+    pool: Option<&'a ThreadPool>,
+    threads: Option<usize>,
+}
+
+impl Workers<'_> {
+    // Number of values handled by a task, a multiple of the size of the merged bins
+    fn task_size(&self, length: usize, merged_size: usize) -> usize {
+        match self.threads {
+            Some(threads) => length.div_ceil(merged_size * threads.max(1)).max(1) * merged_size,
+            None => merged_size,
         }
     }
 
-    fn finish_merge(&self) {
-        // Double the bin size to prepare for the next merging iteration
-        let mut bin_size = self
-            .bin_size
-            .write()
-            .expect("Could not lock the bin size mutex");
-        *bin_size *= 2;
-    }
-    fn get_bin_size(&self) -> usize {
-        let bin_size = self
-            .bin_size
-            .read()
-            .expect("Could not lock the bin size mutex");
-        *bin_size
+    // Threads available to a pass, when there is one task per pair of bins
+    // the merges are split once there are fewer pairs than cores
+    fn threads(&self) -> usize {
+        match (self.threads, self.pool) {
+            (Some(threads), _) => threads.max(1),
+            (None, Some(pool)) => pool.size().max(1),
+            (None, None) => thread::available_parallelism().map_or(1, |n| n.get()),
+        }
     }
 
-    fn get_values(&self) -> Vec<T> {
-        let mut values: Vec<T> = Vec::with_capacity(self.length);
-        for val in &self.values {
-            values.push(val.lock().expect("Could not acquire a value mutex").clone());
+    // Returns the number of tasks once they have all finished
+    fn run<'t>(&self, tasks: impl Iterator<Item = impl FnOnce() + Send + 't>) -> usize {
+        match self.pool {
+            Some(pool) => pool.scoped(|scope| tasks.map(|task| scope.execute(task)).count()),
+            None => thread::scope(|scope| {
+                let handles: Vec<_> = tasks.map(|task| scope.spawn(task)).collect();
+                let num_tasks = handles.len();
+                join_all(handles);
+                num_tasks
+            }),
         }
-        values
     }
-    fn get_bins_positions(vec_pair: Arc<SortVecPair<T>>, id: usize) -> Option<SortThreadData<T>> {
-        let bin_size = *vec_pair
-            .bin_size
-            .read()
-            .expect("could not lock the bin size mutex");
-        let start = id * 2 * bin_size;
-        let mid = start + bin_size;
-        let end = mid + bin_size;
-        if end < vec_pair.length {
-            let bins_positions = BinsPositions { start, mid, end };
-            Some(SortThreadData {
-                vec_pair: Arc::clone(&vec_pair),
-                bins_positions,
-            })
-        } else if mid < vec_pair.length {
-            let bins_positions = BinsPositions {
-                start,
-                mid,
-                end: vec_pair.length.into(),
-            };
-            Some(SortThreadData {
-                vec_pair: Arc::clone(&vec_pair),
-                bins_positions,
-            })
-        } else {
-            None
+}
+
+pub fn merge_sort_parallel<T: SortTraits + Clone>(input: &[T]) -> Vec<T> {
+    merge_sort_parallel_vec(input.to_vec())
+}
+
+/// Sorts an owned vector without copying it first, the elements do not need to be `Clone`.
+pub fn merge_sort_parallel_vec<T: SortTraits>(input: Vec<T>) -> Vec<T> {
+    merge_sort_parallel_impl(input, SortConfig::default(), |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_parallel_with_config<T: SortTraits + Clone>(
+    input: &[T],
+    config: SortConfig,
+) -> Vec<T> {
+    merge_sort_parallel_impl(input.to_vec(), config, |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_parallel_by<T, F>(input: &[T], compare: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_parallel_impl(
+        input.to_vec(),
+        SortConfig::default(),
+        move |a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn merge_sort_parallel_by_key<T, K, F>(input: &[T], f: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_parallel_impl(
+        input.to_vec(),
+        SortConfig::default(),
+        move |a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+/// Sorts the slice in the caller's storage. The elements only need to be `Send + Sync`,
+/// so they can borrow from the caller, like `&str` slices of a request buffer.
+pub fn merge_sort_parallel_in_place<T: SortTraits>(values: &mut [T]) {
+    merge_sort_parallel_in_place_impl(values, SortConfig::default(), &|a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_parallel_in_place_by<T, F>(values: &mut [T], compare: F)
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_parallel_in_place_impl(values, SortConfig::default(), &|a: &T, b: &T| {
+        compare(a, b) == Ordering::Less
+    })
+}
+
+pub fn merge_sort_parallel_in_place_by_key<T, K, F>(values: &mut [T], f: F)
+where
+    T: SortByTraits,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_parallel_in_place_impl(values, SortConfig::default(), &|a: &T, b: &T| f(a) < f(b))
+}
+
+pub fn merge_sort_parallel_limit<T: SortTraits + Clone>(input: &[T], threads: usize) -> Vec<T> {
+    merge_sort_parallel_limit_vec(input.to_vec(), threads)
+}
+
+pub fn merge_sort_parallel_limit_vec<T: SortTraits>(input: Vec<T>, threads: usize) -> Vec<T> {
+    merge_sort_parallel_limit_impl(input, threads, SortConfig::default(), |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_parallel_limit_with_config<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+    config: SortConfig,
+) -> Vec<T> {
+    merge_sort_parallel_limit_impl(input.to_vec(), threads, config, |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_parallel_limit_by<T, F>(input: &[T], threads: usize, compare: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_parallel_limit_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn merge_sort_parallel_limit_by_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_parallel_limit_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+pub fn merge_sort_parallel_limit_in_place<T: SortTraits>(values: &mut [T], threads: usize) {
+    merge_sort_parallel_limit_in_place_impl(
+        values,
+        threads,
+        SortConfig::default(),
+        &|a: &T, b: &T| a < b,
+    )
+}
+
+pub fn merge_sort_parallel_limit_in_place_by<T, F>(values: &mut [T], threads: usize, compare: F)
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_parallel_limit_in_place_impl(
+        values,
+        threads,
+        SortConfig::default(),
+        &|a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn merge_sort_parallel_limit_in_place_by_key<T, K, F>(values: &mut [T], threads: usize, f: F)
+where
+    T: SortByTraits,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_parallel_limit_in_place_impl(
+        values,
+        threads,
+        SortConfig::default(),
+        &|a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+pub fn merge_sort_threadpool<T: SortTraits + Clone>(input: &[T], threads: usize) -> Vec<T> {
+    merge_sort_threadpool_vec(input.to_vec(), threads)
+}
+
+pub fn merge_sort_threadpool_vec<T: SortTraits>(input: Vec<T>, threads: usize) -> Vec<T> {
+    merge_sort_threadpool_impl(input, threads, SortConfig::default(), |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_threadpool_with_config<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+    config: SortConfig,
+) -> Vec<T> {
+    merge_sort_threadpool_impl(input.to_vec(), threads, config, |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_threadpool_by<T, F>(input: &[T], threads: usize, compare: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_threadpool_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn merge_sort_threadpool_by_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_threadpool_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+pub fn merge_sort_threadpool_in_place<T: SortTraits>(values: &mut [T], threads: usize) {
+    merge_sort_threadpool_in_place_impl(values, threads, SortConfig::default(), &|a: &T, b: &T| {
+        a < b
+    })
+}
+
+pub fn merge_sort_threadpool_in_place_by<T, F>(values: &mut [T], threads: usize, compare: F)
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_threadpool_in_place_impl(values, threads, SortConfig::default(), &|a: &T, b: &T| {
+        compare(a, b) == Ordering::Less
+    })
+}
+
+pub fn merge_sort_threadpool_in_place_by_key<T, K, F>(values: &mut [T], threads: usize, f: F)
+where
+    T: SortByTraits,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_threadpool_in_place_impl(values, threads, SortConfig::default(), &|a: &T, b: &T| {
+        f(a) < f(b)
+    })
+}
+
+pub fn merge_sort_threadpool_chunks<T: SortTraits + Clone>(input: &[T], threads: usize) -> Vec<T> {
+    merge_sort_threadpool_chunks_vec(input.to_vec(), threads)
+}
+
+pub fn merge_sort_threadpool_chunks_vec<T: SortTraits>(input: Vec<T>, threads: usize) -> Vec<T> {
+    merge_sort_threadpool_chunks_impl(input, threads, SortConfig::default(), |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_threadpool_chunks_with_config<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+    config: SortConfig,
+) -> Vec<T> {
+    merge_sort_threadpool_chunks_impl(input.to_vec(), threads, config, |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_threadpool_chunks_by<T, F>(input: &[T], threads: usize, compare: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_threadpool_chunks_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn merge_sort_threadpool_chunks_by_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_threadpool_chunks_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+pub fn merge_sort_threadpool_chunks_in_place<T: SortTraits>(values: &mut [T], threads: usize) {
+    merge_sort_threadpool_chunks_in_place_impl(
+        values,
+        threads,
+        SortConfig::default(),
+        &|a: &T, b: &T| a < b,
+    )
+}
+
+pub fn merge_sort_threadpool_chunks_in_place_by<T, F>(values: &mut [T], threads: usize, compare: F)
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_threadpool_chunks_in_place_impl(
+        values,
+        threads,
+        SortConfig::default(),
+        &|a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn merge_sort_threadpool_chunks_in_place_by_key<T, K, F>(values: &mut [T], threads: usize, f: F)
+where
+    T: SortByTraits,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_threadpool_chunks_in_place_impl(
+        values,
+        threads,
+        SortConfig::default(),
+        &|a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+/// Sort of each chunk of `merge_sort_hybrid_with_chunk_sort`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkSort {
+    /// The single-core merge sort, with the given configuration.
+    Merge(SortConfig),
+    /// Timsort, which adapts to chunks made of long sorted runs.
+    Timsort,
+}
+
+impl Default for ChunkSort {
+    fn default() -> Self {
+        ChunkSort::Merge(SortConfig::default())
+    }
+}
+
+impl ChunkSort {
+    fn sort<T, F: LessFn<T>>(self, chunk: &mut [T], is_less: &F) {
+        let mut is_less = |a: &T, b: &T| is_less(a, b);
+        match self {
+            ChunkSort::Merge(config) => merge_sort_impl(chunk, config, &mut is_less),
+            ChunkSort::Timsort => timsort_impl(chunk, &mut is_less),
         }
     }
 }
 
-pub fn merge_sort_parallel<T: SortTraits>(input: &[T]) -> Vec<T> {
-    let sort_vec_pair = SortVecPair::new(input);
-    let sort_vec_pair = Arc::new(sort_vec_pair);
-    while sort_vec_pair.get_bin_size() < input.len() {
-        let mut handles_vec = Vec::new();
-        let mut id = 0;
-        while let Some(sort_thread_data) =
-            SortVecPair::get_bins_positions(Arc::clone(&sort_vec_pair), id)
-        {
-            let handle = thread::spawn(move || merge_bins(sort_thread_data));
-            handles_vec.push(handle);
-            id += 1;
+/// Sorts one chunk per thread with the single-core sort, then merges the chunks
+/// pairwise, every merge being split between all the threads. The threads only
+/// wait for each other once per level of the merge tree, `log2(threads)` times.
+pub fn merge_sort_hybrid<T: SortTraits + Clone>(input: &[T], threads: usize) -> Vec<T> {
+    merge_sort_hybrid_vec(input.to_vec(), threads)
+}
+
+pub fn merge_sort_hybrid_vec<T: SortTraits>(mut input: Vec<T>, threads: usize) -> Vec<T> {
+    merge_sort_hybrid_in_place(&mut input, threads);
+    input
+}
+
+pub fn merge_sort_hybrid_with_chunk_sort<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+    chunk_sort: ChunkSort,
+) -> Vec<T> {
+    let mut values = input.to_vec();
+    merge_sort_hybrid_impl(&mut values, threads, chunk_sort, &|a: &T, b: &T| a < b);
+    values
+}
+
+pub fn merge_sort_hybrid_by<T, F>(input: &[T], threads: usize, compare: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    let mut values = input.to_vec();
+    merge_sort_hybrid_in_place_by(&mut values, threads, compare);
+    values
+}
+
+pub fn merge_sort_hybrid_by_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    let mut values = input.to_vec();
+    merge_sort_hybrid_in_place_by_key(&mut values, threads, f);
+    values
+}
+
+pub fn merge_sort_hybrid_in_place<T: SortTraits>(values: &mut [T], threads: usize) {
+    merge_sort_hybrid_impl(values, threads, ChunkSort::default(), &|a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_hybrid_in_place_by<T, F>(values: &mut [T], threads: usize, compare: F)
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    merge_sort_hybrid_impl(values, threads, ChunkSort::default(), &|a: &T, b: &T| {
+        compare(a, b) == Ordering::Less
+    })
+}
+
+pub fn merge_sort_hybrid_in_place_by_key<T, K, F>(values: &mut [T], threads: usize, f: F)
+where
+    T: SortByTraits,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    merge_sort_hybrid_impl(values, threads, ChunkSort::default(), &|a: &T, b: &T| {
+        f(a) < f(b)
+    })
+}
+
+/// Computes the keys once on the calling thread, then sorts them in parallel
+/// before reordering the values.
+pub fn merge_sort_parallel_by_cached_key<T, K, F>(input: &[T], f: F) -> Vec<T>
+where
+    T: Clone,
+    K: SortByTraits + Ord,
+    F: FnMut(&T) -> K,
+{
+    sort_by_cached_key_impl(input, f, |keys| {
+        merge_sort_parallel_impl(keys, SortConfig::default(), cached_key_less)
+    })
+}
+
+pub fn merge_sort_parallel_limit_by_cached_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: Clone,
+    K: SortByTraits + Ord,
+    F: FnMut(&T) -> K,
+{
+    sort_by_cached_key_impl(input, f, |keys| {
+        merge_sort_parallel_limit_impl(keys, threads, SortConfig::default(), cached_key_less)
+    })
+}
+
+pub fn merge_sort_threadpool_by_cached_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: Clone,
+    K: SortByTraits + Ord,
+    F: FnMut(&T) -> K,
+{
+    sort_by_cached_key_impl(input, f, |keys| {
+        merge_sort_threadpool_impl(keys, threads, SortConfig::default(), cached_key_less)
+    })
+}
+
+pub fn merge_sort_threadpool_chunks_by_cached_key<T, K, F>(
+    input: &[T],
+    threads: usize,
+    f: F,
+) -> Vec<T>
+where
+    T: Clone,
+    K: SortByTraits + Ord,
+    F: FnMut(&T) -> K,
+{
+    sort_by_cached_key_impl(input, f, |keys| {
+        merge_sort_threadpool_chunks_impl(keys, threads, SortConfig::default(), cached_key_less)
+    })
+}
+
+// Pairs each key with the position of its value, so that the sorted pairs give
+// the order of the values
+fn sort_by_cached_key_impl<T: Clone, K, F: FnMut(&T) -> K>(
+    input: &[T],
+    f: F,
+    sort_keys: impl FnOnce(Vec<(K, usize)>) -> Vec<(K, usize)>,
+) -> Vec<T> {
+    let keys = sort_keys(input.iter().map(f).zip(0..).collect());
+    keys.into_iter().map(|(_, id)| input[id].clone()).collect()
+}
+
+fn cached_key_less<K: Ord>(a: &(K, usize), b: &(K, usize)) -> bool {
+    a.0 < b.0
+}
+
+/// Indices that would sort the values, equal values keep their original order.
+/// The indices are sorted in parallel, the values are left untouched.
+pub fn merge_argsort_threadpool<T: SortTraits>(values: &[T], threads: usize) -> Vec<usize> {
+    argsort_threadpool_impl(values, threads, |a: &T, b: &T| a < b)
+}
+
+pub fn merge_argsort_threadpool_by<T, F>(values: &[T], threads: usize, compare: F) -> Vec<usize>
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    argsort_threadpool_impl(values, threads, move |a: &T, b: &T| {
+        compare(a, b) == Ordering::Less
+    })
+}
+
+fn argsort_threadpool_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &[T],
+    threads: usize,
+    is_less: F,
+) -> Vec<usize> {
+    merge_sort_threadpool_chunks_impl(
+        (0..values.len()).collect(),
+        threads.max(1),
+        SortConfig::default(),
+        move |a: &usize, b: &usize| is_less(&values[*a], &values[*b]),
+    )
+}
+
+/// Same as `single_core_sort::merge_sort_with_inversions`, with the merges of each
+/// pass split between the threads of the pool. Counts the inversions exactly,
+/// so the merges start from single elements instead of sorted blocks.
+pub fn merge_sort_threadpool_with_inversions<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+) -> (Vec<T>, u64) {
+    let config = SortConfig {
+        small_sort_threshold: 1,
+        ..SortConfig::default()
+    };
+    let mut values = input.to_vec();
+    let threads = threads.max(1);
+    let pool = ThreadPool::new(threads);
+    let workers = Workers {
+        pool: Some(&pool),
+        threads: Some(threads),
+    };
+    let inversions = sort_on_workers(&mut values, config, &|a: &T, b: &T| a < b, workers, &mut ());
+    (values, inversions)
+}
+
+/// Number of pairs of values out of order, the values are copied and sorted in parallel.
+pub fn count_inversions_threadpool<T: SortTraits + Clone>(values: &[T], threads: usize) -> u64 {
+    merge_sort_threadpool_with_inversions(values, threads).1
+}
+
+/// Same as `single_core_sort::set_op`, for large inputs. The inputs are cut at the
+/// same values into one part per thread, equal elements always landing in the same
+/// part, then the parts are processed by the thread pool.
+pub fn set_op_threadpool<T: SortTraits + Clone>(
+    a: &[T],
+    b: &[T],
+    op: SetOp,
+    semantics: Semantics,
+    threads: usize,
+) -> Vec<T> {
+    set_op_threadpool_impl(a, b, op, semantics, threads, |x: &T, y: &T| x < y)
+}
+
+pub fn set_op_threadpool_by<T, F>(
+    a: &[T],
+    b: &[T],
+    op: SetOp,
+    semantics: Semantics,
+    threads: usize,
+    compare: F,
+) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    set_op_threadpool_impl(a, b, op, semantics, threads, move |x: &T, y: &T| {
+        compare(x, y) == Ordering::Less
+    })
+}
+
+fn set_op_threadpool_impl<T: SortByTraits + Clone, F: LessFn<T>>(
+    a: &[T],
+    b: &[T],
+    op: SetOp,
+    semantics: Semantics,
+    threads: usize,
+    is_less: F,
+) -> Vec<T> {
+    // Part k starts at the first element not less than the k-th pivot,
+    // the pivots being spread over the longest input
+    let pivots = if a.len() >= b.len() { a } else { b };
+    let threads = threads.min(pivots.len()).max(1);
+    let mut bounds = vec![(0, 0)];
+    for k in 1..threads {
+        let pivot = &pivots[k * pivots.len() / threads];
+        bounds.push((
+            a.partition_point(|v| is_less(v, pivot)),
+            b.partition_point(|v| is_less(v, pivot)),
+        ));
+    }
+    bounds.push((a.len(), b.len()));
+
+    let is_less = &is_less;
+    let mut parts: Vec<Vec<T>> = (0..threads).map(|_| Vec::new()).collect();
+    let threadpool = ThreadPool::new(threads);
+    threadpool.scoped(|scope| {
+        for (part, bounds) in parts.iter_mut().zip(bounds.windows(2)) {
+            let ((start_a, start_b), (end_a, end_b)) = (bounds[0], bounds[1]);
+            let (a, b) = (&a[start_a..end_a], &b[start_b..end_b]);
+            scope.execute(move || {
+                *part = set_op_impl(a, b, op, semantics, &mut |x: &T, y: &T| is_less(x, y));
+            });
         }
-        for handle in handles_vec {
-            _ = handle.join();
+    });
+    parts.into_iter().flatten().collect()
+}
+
+/// Same as `merge_sort_parallel`, along with what the sort did.
+pub fn merge_sort_parallel_with_stats<T: SortTraits + Clone>(input: &[T]) -> (Vec<T>, SortStats) {
+    let workers = Workers {
+        pool: None,
+        threads: None,
+    };
+    sort_with_stats(input, workers)
+}
+
+pub fn merge_sort_threadpool_with_stats<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+) -> (Vec<T>, SortStats) {
+    let pool = ThreadPool::new(threads);
+    let workers = Workers {
+        pool: Some(&pool),
+        threads: None,
+    };
+    sort_with_stats(input, workers)
+}
+
+pub fn merge_sort_threadpool_chunks_with_stats<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+) -> (Vec<T>, SortStats) {
+    let pool = ThreadPool::new(threads);
+    let workers = Workers {
+        pool: Some(&pool),
+        threads: Some(threads),
+    };
+    sort_with_stats(input, workers)
+}
+
+fn sort_with_stats<T: SortTraits + Clone>(input: &[T], workers: Workers) -> (Vec<T>, SortStats) {
+    let mut recorder = StatsRecorder::default();
+    let mut values = input.to_vec();
+    // The copy of the input
+    recorder.allocated(usize::from(!values.is_empty()));
+    // Only the sorts with statistics pay for the shared counter
+    let comparisons = AtomicU64::new(0);
+    let is_less = |a: &T, b: &T| {
+        comparisons.fetch_add(1, atomic::Ordering::Relaxed);
+        a < b
+    };
+    sort_on_workers(
+        &mut values,
+        SortConfig::default(),
+        &is_less,
+        workers,
+        &mut recorder,
+    );
+    recorder.stats.comparisons = comparisons.into_inner();
+    (values, recorder.stats)
+}
+
+/// Same as `single_core_sort::sort_columns`, with the work split between `threads`
+/// scoped threads: each one sorts a chunk of the row indices, the chunks are merged
+/// with a k-way merge, then the columns are permuted concurrently.
+pub fn sort_columns_parallel(
+    keys: &mut [&mut (dyn KeyColumn + Send + Sync)],
+    columns: &mut [&mut (dyn Column + Send)],
+    threads: usize,
+) {
+    let length = columns_length(keys, columns);
+    let compare = |a: &usize, b: &usize| compare_rows(keys, *a, *b);
+    let mut indices: Vec<usize> = (0..length).collect();
+    let chunk_size = length.div_ceil(threads.max(1)).max(1);
+    thread::scope(|scope| {
+        for chunk in indices.chunks_mut(chunk_size) {
+            scope.spawn(move || merge_sort_in_place_by(chunk, compare));
         }
-        // Copy buffer into values vector, increase bin size
-        sort_vec_pair.finish_merge();
+    });
+    let chunks: Vec<&[usize]> = indices.chunks(chunk_size).collect();
+    let permutation = merge_many_by(&chunks, compare);
+
+    let mut all_columns: Vec<&mut (dyn Column + Send)> = Vec::new();
+    for key in keys.iter_mut() {
+        all_columns.push(&mut **key);
+    }
+    for column in columns.iter_mut() {
+        all_columns.push(&mut **column);
     }
-    sort_vec_pair.get_values()
+    let columns_per_thread = all_columns.len().div_ceil(threads.max(1)).max(1);
+    let permutation = &permutation;
+    thread::scope(|scope| {
+        for columns in all_columns.chunks_mut(columns_per_thread) {
+            scope.spawn(move || {
+                for column in columns {
+                    column.permute(permutation);
+                }
+            });
+        }
+    });
+}
+
+/// Sorts floats in the total order given by `order`, see `single_core_sort::FloatOrder`.
+pub fn merge_sort_parallel_floats<F: SortFloat + SortByTraits>(
+    input: &[F],
+    order: FloatOrder,
+) -> Result<Vec<F>, NanError> {
+    order.check(input)?;
+    Ok(merge_sort_parallel_impl(
+        input.to_vec(),
+        SortConfig::default(),
+        move |a: &F, b: &F| order.compare(a, b) == Ordering::Less,
+    ))
+}
+
+pub fn merge_sort_parallel_limit_floats<F: SortFloat + SortByTraits>(
+    input: &[F],
+    threads: usize,
+    order: FloatOrder,
+) -> Result<Vec<F>, NanError> {
+    order.check(input)?;
+    Ok(merge_sort_parallel_limit_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &F, b: &F| order.compare(a, b) == Ordering::Less,
+    ))
+}
+
+pub fn merge_sort_threadpool_floats<F: SortFloat + SortByTraits>(
+    input: &[F],
+    threads: usize,
+    order: FloatOrder,
+) -> Result<Vec<F>, NanError> {
+    order.check(input)?;
+    Ok(merge_sort_threadpool_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &F, b: &F| order.compare(a, b) == Ordering::Less,
+    ))
+}
+
+pub fn merge_sort_threadpool_chunks_floats<F: SortFloat + SortByTraits>(
+    input: &[F],
+    threads: usize,
+    order: FloatOrder,
+) -> Result<Vec<F>, NanError> {
+    order.check(input)?;
+    Ok(merge_sort_threadpool_chunks_impl(
+        input.to_vec(),
+        threads,
+        SortConfig::default(),
+        move |a: &F, b: &F| order.compare(a, b) == Ordering::Less,
+    ))
+}
+
+fn merge_sort_parallel_impl<T: SortByTraits, F: LessFn<T>>(
+    mut input: Vec<T>,
+    config: SortConfig,
+    is_less: F,
+) -> Vec<T> {
+    merge_sort_parallel_in_place_impl(&mut input, config, &is_less);
+    input
+}
+
+fn merge_sort_parallel_in_place_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    config: SortConfig,
+    is_less: &F,
+) {
+    // Each pass spawns one thread per block or pair of bins
+    let workers = Workers {
+        pool: None,
+        threads: None,
+    };
+    sort_on_workers(values, config, is_less, workers, &mut ());
+}
+
+fn merge_sort_parallel_limit_impl<T: SortByTraits, F: LessFn<T>>(
+    mut input: Vec<T>,
+    threads: usize,
+    config: SortConfig,
+    is_less: F,
+) -> Vec<T> {
+    merge_sort_parallel_limit_in_place_impl(&mut input, threads, config, &is_less);
+    input
 }
 
 // Attempt to simplify the threadpool approach to improve performance,
 // at the cost of reinstancing the threads at each iteration
 // Goal: go past the single threaded performance
-pub fn merge_sort_parallel_limit<T: SortTraits>(input: &[T], threads: usize) -> Vec<T> {
-    let sort_vec_pair = SortVecPair::new(input);
-    let sort_vec_pair = Arc::new(sort_vec_pair);
-    let input_length = input.len();
-    while sort_vec_pair.get_bin_size() < input_length {
-        let bin_size = sort_vec_pair.get_bin_size();
-        let mut handles_vec = Vec::new();
-        let max_ops = input_length.div_ceil(threads * 2 * bin_size);
-        for ct in 0..threads {
-            let sort_vec_pair = Arc::clone(&sort_vec_pair);
-            let handle = thread::spawn(move || {
-                let start = ct * max_ops;
-                let limit = start + max_ops;
-                for id in start..limit {
-                    match SortVecPair::get_bins_positions(Arc::clone(&sort_vec_pair), id) {
-                        Some(sort_thread_data) => {
-                            merge_bins(sort_thread_data);
-                        }
-                        None => break,
-                    };
+fn merge_sort_parallel_limit_in_place_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    threads: usize,
+    config: SortConfig,
+    is_less: &F,
+) {
+    let workers = Workers {
+        pool: None,
+        threads: Some(threads),
+    };
+    sort_on_workers(values, config, is_less, workers, &mut ());
+}
+
+fn merge_sort_threadpool_impl<T: SortByTraits, F: LessFn<T>>(
+    mut input: Vec<T>,
+    threads: usize,
+    config: SortConfig,
+    is_less: F,
+) -> Vec<T> {
+    merge_sort_threadpool_in_place_impl(&mut input, threads, config, &is_less);
+    input
+}
+
+fn merge_sort_threadpool_in_place_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    threads: usize,
+    config: SortConfig,
+    is_less: &F,
+) {
+    // Each pass sends one task per block or pair of bins to the pool
+    let pool = ThreadPool::new(threads);
+    let workers = Workers {
+        pool: Some(&pool),
+        threads: None,
+    };
+    sort_on_workers(values, config, is_less, workers, &mut ());
+}
+
+fn merge_sort_threadpool_chunks_impl<T: SortByTraits, F: LessFn<T>>(
+    mut input: Vec<T>,
+    threads: usize,
+    config: SortConfig,
+    is_less: F,
+) -> Vec<T> {
+    merge_sort_threadpool_chunks_in_place_impl(&mut input, threads, config, &is_less);
+    input
+}
+
+// Attempt to speed up the parallel processing by splitting the code into bigger tasks
+fn merge_sort_threadpool_chunks_in_place_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    threads: usize,
+    config: SortConfig,
+    is_less: &F,
+) {
+    let pool = ThreadPool::new(threads);
+    let workers = Workers {
+        pool: Some(&pool),
+        threads: Some(threads),
+    };
+    sort_on_workers(values, config, is_less, workers, &mut ());
+}
+
+fn merge_sort_hybrid_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    threads: usize,
+    chunk_sort: ChunkSort,
+    is_less: &F,
+) {
+    let threads = threads.max(1);
+    let pool = ThreadPool::new(threads);
+    let workers = Workers {
+        pool: Some(&pool),
+        threads: Some(threads),
+    };
+    let chunk_size = values.len().div_ceil(threads).max(1);
+    workers.run(
+        values
+            .chunks_mut(chunk_size)
+            .map(|chunk| move || chunk_sort.sort(chunk, is_less)),
+    );
+    merge_on_workers(values, chunk_size, is_less, workers, &mut ());
+}
+
+// Sorts the small blocks in place, then merges them on the same workers.
+// Returns the number of inversions between the merged bins.
+fn sort_on_workers<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    config: SortConfig,
+    is_less: &F,
+    workers: Workers,
+    observer: &mut impl PassObserver,
+) -> u64 {
+    let length = values.len();
+    let block_size = config.block_size();
+    if block_size > 1 && length > 1 {
+        observer.start_pass();
+        let task_size = workers.task_size(length, block_size);
+        let tasks = values.chunks_mut(task_size).map(|blocks| {
+            move || {
+                for block in blocks.chunks_mut(block_size) {
+                    config.sort_block(block, &mut |a: &T, b: &T| is_less(a, b));
                 }
-            });
-            handles_vec.push(handle);
-        }
-        for handle in handles_vec {
-            _ = handle.join();
-        }
-        // Copy buffer into values vector, increase bin size
-        sort_vec_pair.finish_merge();
+            }
+        });
+        observer.dispatched(workers.run(tasks));
+        observer.finish_pass();
     }
-    sort_vec_pair.get_values()
+    merge_on_workers(values, block_size, is_less, workers, observer)
 }
 
-pub fn merge_sort_threadpool<T: SortTraits>(input: &[T], threads: usize) -> Vec<T> {
-    let sort_vec_pair = SortVecPair::new(input);
-    let sort_vec_pair = Arc::new(sort_vec_pair);
-    let threadpool = ThreadPool::new(threads);
-    while sort_vec_pair.get_bin_size() < input.len() {
-        // Channel to keep track of the pool progress through the tasks
-        let (task_progress_write, task_progress_read) = mpsc::channel();
-        let task_progress_write = Arc::new(task_progress_write);
-        let mut num_tasks = 0;
-        while let Some(sort_thread_data) =
-            SortVecPair::get_bins_positions(Arc::clone(&sort_vec_pair), num_tasks)
-        {
-            let task_progress_write = Arc::clone(&task_progress_write);
-            threadpool.execute(move || {
-                merge_bins(sort_thread_data);
-                task_progress_write.send(()).unwrap();
-            });
-            num_tasks += 1;
-        }
-        // Wait until the tasks finish
-        for _ in 0..num_tasks {
-            task_progress_read.recv().unwrap();
-        }
-        // Copy buffer into values vector, increase bin size
+// Merges the sorted bins of `bin_size` values back and forth between the values
+// and a buffer, waiting for every task of a pass before the next one.
+// Returns the number of inversions between the merged bins.
+fn merge_on_workers<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    bin_size: usize,
+    is_less: &F,
+    workers: Workers,
+    observer: &mut impl PassObserver,
+) -> u64 {
+    let length = values.len();
+    if bin_size >= length {
+        return 0;
+    }
+    let mut buffer = Box::new_uninit_slice(length);
+    observer.allocated(1);
+    let mut sort_vec_pair = SortVecPair::new(values, &mut buffer, bin_size);
+    let inversions = AtomicU64::new(0);
+    while sort_vec_pair.get_bin_size() < length {
+        observer.start_pass();
+        let bin_size = sort_vec_pair.get_bin_size();
+        let pairs = length.div_ceil(2 * bin_size);
+        let threads = workers.threads();
+        let (source, destination) = sort_vec_pair.get_source_and_destination();
+        let inversions = &inversions;
+        let num_tasks = if pairs < threads {
+            // Too few pairs of bins to keep the threads busy, each merge is split
+            let tasks = source
+                .chunks(2 * bin_size)
+                .zip(destination.chunks_mut(2 * bin_size))
+                .flat_map(|(bins, buf)| {
+                    split_merge(bins, buf, bin_size, threads.div_ceil(pairs), is_less)
+                })
+                .map(|part| {
+                    move || {
+                        let MergePart {
+                            bin1,
+                            bin2,
+                            buf,
+                            uncounted_inversions,
+                        } = part;
+                        // SAFETY: the parts of a merge are disjoint, see `merge_range`
+                        let count = unsafe {
+                            merge_bins(bin1, bin2, buf, &mut |a: &T, b: &T| is_less(a, b))
+                        };
+                        inversions
+                            .fetch_add(count + uncounted_inversions, atomic::Ordering::Relaxed);
+                    }
+                });
+            workers.run(tasks)
+        } else {
+            let task_size = workers.task_size(length, 2 * bin_size);
+            let tasks = source
+                .chunks(task_size)
+                .zip(destination.chunks_mut(task_size))
+                .map(|(source, destination)| {
+                    move || {
+                        let count = merge_range(source, destination, bin_size, is_less);
+                        inversions.fetch_add(count, atomic::Ordering::Relaxed);
+                    }
+                });
+            workers.run(tasks)
+        };
+        observer.dispatched(num_tasks);
+        // Swap the values and buffer roles, increase bin size
         sort_vec_pair.finish_merge();
+        observer.moved(length);
+        observer.finish_pass();
+    }
+    // Dropping the pair moves the values back from the buffer
+    if sort_vec_pair.sorted_in_buffer {
+        observer.moved(length);
     }
-    sort_vec_pair.get_values()
+    inversions.into_inner()
 }
 
-// Attempt to speed up the parallel processing by splitting the code into bigger tasks
-pub fn merge_sort_threadpool_chunks<T: SortTraits>(input: &[T], threads: usize) -> Vec<T> {
-    let sort_vec_pair = SortVecPair::new(input);
-    let sort_vec_pair = Arc::new(sort_vec_pair);
-    let threadpool = ThreadPool::new(threads);
-    let input_len = input.len();
-    while sort_vec_pair.get_bin_size() < input_len {
-        // Channel to keep track of the pool progress through the tasks
-        let (task_progress_write, task_progress_read) = mpsc::channel();
-        let task_progress_write = Arc::new(task_progress_write);
-        let mut num_tasks = 0;
-        let bin_size = sort_vec_pair.get_bin_size();
-        let num_ops_per_thread = input_len.div_ceil(2 * bin_size * threads);
-        for ct in 0..threads {
-            let sort_vec_pair = Arc::clone(&sort_vec_pair);
-            let task_progress_write = Arc::clone(&task_progress_write);
-            threadpool.execute(move || {
-                let mut id = ct * num_ops_per_thread;
-                while id < (ct + 1) * num_ops_per_thread {
-                    if let Some(sort_thread_data) =
-                        SortVecPair::get_bins_positions(Arc::clone(&sort_vec_pair), id)
-                    {
-                        merge_bins(sort_thread_data);
-                    };
-                    id += 1;
-                }
-                task_progress_write.send(()).unwrap();
-            });
-            num_tasks += 1;
-        }
-        // Wait until the tasks finish
-        for _ in 0..num_tasks {
-            task_progress_read.recv().unwrap();
-        }
-        // Copy buffer into values vector, increase bin size
-        sort_vec_pair.finish_merge();
+// Merges each pair of bins of the chunk into the same positions of the destination,
+// a lone last bin is copied as is
+fn merge_range<T, F: LessFn<T>>(
+    source: &[T],
+    destination: &mut [MaybeUninit<T>],
+    bin_size: usize,
+    is_less: &F,
+) -> u64 {
+    let mut inversions = 0;
+    for (bins, buf) in source
+        .chunks(2 * bin_size)
+        .zip(destination.chunks_mut(2 * bin_size))
+    {
+        let (bin1, bin2) = bins.split_at(bin_size.min(bins.len()));
+        // SAFETY: the chunks of a pass are disjoint, and the values and the buffer
+        // only swap roles once every task of the pass has finished
+        inversions += unsafe { merge_bins(bin1, bin2, buf, &mut |a: &T, b: &T| is_less(a, b)) };
     }
-    sort_vec_pair.get_values()
-}
-
-fn merge_bins<T: SortTraits>(sort_thread_data: SortThreadData<T>) {
-    let SortThreadData {
-        vec_pair,
-        bins_positions,
-    } = sort_thread_data;
-    let bin1 = &vec_pair.values[bins_positions.start..bins_positions.mid];
-    let bin2 = &vec_pair.values[bins_positions.mid..bins_positions.end];
-    let buf = &vec_pair.buffer[bins_positions.start..bins_positions.end];
-    let mut id1 = 0;
-    let mut id2 = 0;
-    for min_val in buf {
-        let mut min_val = min_val.lock().unwrap();
-        if id1 >= bin1.len() {
-            *min_val = bin2[id2].lock().unwrap().clone().into();
-            id2 += 1;
-        } else if id2 >= bin2.len() {
-            *min_val = bin1[id1].lock().unwrap().clone().into();
-            id1 += 1;
+    inversions
+}
+
+// Part of the merge of two bins, independent of the other parts
+struct MergePart<'a, T> {
+    bin1: &'a [T],
+    bin2: &'a [T],
+    buf: &'a mut [MaybeUninit<T>],
+    // Values of the first bin after this part, greater than every value
+    // the part takes from the second bin
+    uncounted_inversions: u64,
+}
+
+// Cuts the merge of a pair of bins into `parts` parts of the same output size,
+// each part merging the ends of the bins co-ranked with its output range
+fn split_merge<'a, T, F: LessFn<T>>(
+    bins: &'a [T],
+    mut buf: &'a mut [MaybeUninit<T>],
+    bin_size: usize,
+    parts: usize,
+    is_less: &F,
+) -> Vec<MergePart<'a, T>> {
+    let (bin1, bin2) = bins.split_at(bin_size.min(bins.len()));
+    let parts = parts.min(bins.len()).max(1);
+    let mut merge_parts = Vec::with_capacity(parts);
+    let (mut start1, mut start2) = (0, 0);
+    for part in 1..=parts {
+        let end = part * bins.len() / parts;
+        let end1 = co_rank(bin1, bin2, end, is_less);
+        let end2 = end - end1;
+        let (part_buf, rest) = buf.split_at_mut(end - start1 - start2);
+        buf = rest;
+        merge_parts.push(MergePart {
+            bin1: &bin1[start1..end1],
+            bin2: &bin2[start2..end2],
+            buf: part_buf,
+            uncounted_inversions: ((bin1.len() - end1) * (end2 - start2)) as u64,
+        });
+        (start1, start2) = (end1, end2);
+    }
+    merge_parts
+}
+
+// Number of values of the first bin among the first `rank` values of the merge.
+// Equal values are taken from the first bin first, like `merge_bins` does.
+fn co_rank<T, F: LessFn<T>>(bin1: &[T], bin2: &[T], rank: usize, is_less: &F) -> usize {
+    let mut low = rank.saturating_sub(bin2.len());
+    let mut high = rank.min(bin1.len());
+    // Smallest count such that the last value taken from the second bin
+    // is strictly smaller than the next value of the first one
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if is_less(&bin2[rank - mid - 1], &bin1[mid]) {
+            high = mid;
         } else {
-            let val1 = bin1[id1].lock().unwrap().clone();
-            let val2 = bin2[id2].lock().unwrap().clone();
-            if val1 <= val2 {
-                *min_val = val1.into();
-                id1 += 1;
-            } else {
-                *min_val = val2.into();
-                id2 += 1;
-            }
+            low = mid + 1;
+        }
+    }
+    low
+}
+
+// Waits for every thread before propagating a panic,
+// so that the first panic is the one seen by the caller
+fn join_all(handles: Vec<ScopedJoinHandle<'_, ()>>) {
+    let mut first_panic = None;
+    for handle in handles {
+        if let Err(payload) = handle.join() {
+            first_panic.get_or_insert(payload);
         }
     }
-    // Reinject values in the sorted section of the values in parallel
-    // should be faster than doing it all on one thread at the end
-    let vals = &vec_pair.values[bins_positions.start..bins_positions.end];
-    for (v, b) in std::iter::zip(vals, buf) {
-        let mut vl = v
-            .lock()
-            .expect("Value within a sorted bin should be accessible and lockable");
-        let bl = b
-            .lock()
-            .expect("Buffer values within a sorted bin should be accessible and lockable");
-        *vl = bl.clone();
+    if let Some(payload) = first_panic {
+        panic::resume_unwind(payload);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::single_core_sort::{NanPolicy, SignedZero, SmallSort, ThenBy, by};
+    use crate::test_utils::scrambled;
+    use sample_sort::sample_sort_impl;
+    use std::panic::AssertUnwindSafe;
+    use std::sync::Arc;
 
     #[test]
     fn sort_small_vec_parallel() {
@@ -299,4 +1160,443 @@ mod tests {
             vec![1, 3, 12, 15, 24, 25, 37, 53, 56]
         );
     }
+
+    #[test]
+    fn sort_small_vec_by() {
+        let test_vec = vec![15, 53, 1, 24, 25, 3, 37, 12, 56];
+        let expected = vec![56, 53, 37, 25, 24, 15, 12, 3, 1];
+        assert_eq!(merge_sort_parallel_by(&test_vec, |a, b| b.cmp(a)), expected);
+        assert_eq!(
+            merge_sort_parallel_limit_by(&test_vec, 4, |a, b| b.cmp(a)),
+            expected
+        );
+        assert_eq!(
+            merge_sort_threadpool_by(&test_vec, 4, |a, b| b.cmp(a)),
+            expected
+        );
+        assert_eq!(
+            merge_sort_threadpool_chunks_by(&test_vec, 4, |a, b| b.cmp(a)),
+            expected
+        );
+        // Composed comparators can be shared between the threads
+        let compare = by(|v: &i32| v % 2).then_desc(|v| *v);
+        assert_eq!(
+            merge_sort_threadpool_by(&test_vec, 4, compare),
+            vec![56, 24, 12, 53, 37, 25, 15, 3, 1]
+        );
+    }
+
+    #[test]
+    fn sort_small_vec_by_key_is_stable() {
+        let test_vec = vec![(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (0, 'e')];
+        let expected = vec![(0, 'e'), (1, 'b'), (1, 'd'), (2, 'a'), (2, 'c')];
+        assert_eq!(merge_sort_parallel_by_key(&test_vec, |v| v.0), expected);
+        assert_eq!(
+            merge_sort_parallel_limit_by_key(&test_vec, 4, |v| v.0),
+            expected
+        );
+        assert_eq!(
+            merge_sort_threadpool_by_key(&test_vec, 4, |v| v.0),
+            expected
+        );
+        assert_eq!(
+            merge_sort_threadpool_chunks_by_key(&test_vec, 4, |v| v.0),
+            expected
+        );
+        assert_eq!(
+            merge_sort_parallel_by_cached_key(&test_vec, |v| v.0),
+            expected
+        );
+        assert_eq!(
+            merge_sort_parallel_limit_by_cached_key(&test_vec, 4, |v| v.0),
+            expected
+        );
+        assert_eq!(
+            merge_sort_threadpool_by_cached_key(&test_vec, 4, |v| v.0),
+            expected
+        );
+        assert_eq!(
+            merge_sort_threadpool_chunks_by_cached_key(&test_vec, 4, |v| v.0),
+            expected
+        );
+    }
+
+    #[test]
+    fn sort_odd_and_even_pass_counts() {
+        // Covers lone last bins and results left in the buffer
+        for length in 0..40 {
+            let test_vec: Vec<u32> = scrambled(length, 31).collect();
+            let mut expected = test_vec.clone();
+            expected.sort();
+            assert_eq!(merge_sort_parallel(&test_vec), expected);
+            assert_eq!(merge_sort_parallel_limit(&test_vec, 3), expected);
+            assert_eq!(merge_sort_threadpool(&test_vec, 3), expected);
+            assert_eq!(merge_sort_threadpool_chunks(&test_vec, 3), expected);
+        }
+    }
+
+    #[test]
+    fn sort_with_config() {
+        let test_vec: Vec<u32> = scrambled(300, 101).collect();
+        let mut expected = test_vec.clone();
+        expected.sort();
+        for small_sort in [SmallSort::Insertion, SmallSort::Network] {
+            for small_sort_threshold in [0, 3, 8, 13, 16, 600] {
+                let config = SortConfig {
+                    small_sort,
+                    small_sort_threshold,
+                    ..SortConfig::default()
+                };
+                assert_eq!(merge_sort_parallel_with_config(&test_vec, config), expected);
+                assert_eq!(
+                    merge_sort_parallel_limit_with_config(&test_vec, 3, config),
+                    expected
+                );
+                assert_eq!(
+                    merge_sort_threadpool_with_config(&test_vec, 3, config),
+                    expected
+                );
+                assert_eq!(
+                    merge_sort_threadpool_chunks_with_config(&test_vec, 3, config),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn count_inversions_matches_single_core() {
+        for length in [0, 1, 2, 100, 1000] {
+            let test_vec: Vec<u32> = scrambled(length, 101).collect();
+            let expected = crate::single_core_sort::count_inversions(&test_vec);
+            for threads in [0, 1, 3, 4] {
+                let (sorted, inversions) =
+                    merge_sort_threadpool_with_inversions(&test_vec, threads);
+                assert_eq!(inversions, expected, "length {length}, {threads} threads");
+                assert!(sorted.is_sorted());
+                assert_eq!(count_inversions_threadpool(&test_vec, threads), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn set_op_matches_single_core() {
+        let a: Vec<u32> = (0..500).map(|i| i / 3).collect();
+        let b: Vec<u32> = (0..300).map(|i| i * i / 200).collect();
+        for op in [
+            SetOp::Union,
+            SetOp::Intersection,
+            SetOp::Difference,
+            SetOp::SymmetricDifference,
+        ] {
+            for semantics in [Semantics::Set, Semantics::Multiset] {
+                let expected = crate::single_core_sort::set_op(&a, &b, op, semantics);
+                for threads in [1, 3, 8] {
+                    assert_eq!(
+                        set_op_threadpool(&a, &b, op, semantics, threads),
+                        expected,
+                        "{op:?} {semantics:?} with {threads} threads"
+                    );
+                    assert_eq!(
+                        set_op_threadpool(&b, &a, op, semantics, threads),
+                        crate::single_core_sort::set_op(&b, &a, op, semantics)
+                    );
+                }
+            }
+        }
+        let descending = set_op_threadpool_by(
+            &[5, 4, 4, 2],
+            &[4, 3],
+            SetOp::Union,
+            Semantics::Set,
+            2,
+            |x: &u32, y: &u32| y.cmp(x),
+        );
+        assert_eq!(descending, vec![5, 4, 3, 2]);
+        assert!(set_op_threadpool::<u32>(&[], &[], SetOp::Union, Semantics::Set, 4).is_empty());
+    }
+
+    #[test]
+    fn argsort_is_stable() {
+        let test_vec: Vec<u32> = scrambled(300, 37).collect();
+        let expected = crate::single_core_sort::merge_argsort(&test_vec);
+        for threads in [0, 1, 4] {
+            assert_eq!(merge_argsort_threadpool(&test_vec, threads), expected);
+        }
+        let expected = crate::single_core_sort::merge_argsort_by(&test_vec, |a, b| b.cmp(a));
+        assert_eq!(
+            merge_argsort_threadpool_by(&test_vec, 0, |a, b| b.cmp(a)),
+            expected
+        );
+    }
+
+    #[test]
+    fn sort_columns_parallel_test() {
+        let keys: Vec<u64> = scrambled(500, 13).collect();
+        let versions: Vec<u32> = (0..500).map(|i| (i * 31) % 7).collect();
+        let names: Vec<String> = (0..500).map(|i| format!("{i}")).collect();
+        let mut expected = (keys.clone(), versions.clone(), names.clone());
+        crate::single_core_sort::sort_columns(
+            &mut [&mut expected.0, &mut expected.1],
+            &mut [&mut expected.2],
+        );
+        for threads in [1, 3, 8] {
+            let (mut keys, mut versions, mut names) =
+                (keys.clone(), versions.clone(), names.clone());
+            sort_columns_parallel(&mut [&mut keys, &mut versions], &mut [&mut names], threads);
+            assert_eq!((keys, versions, names), expected, "{threads} threads");
+        }
+    }
+
+    #[test]
+    fn sort_floats() {
+        let test_vec = vec![
+            2.5,
+            f64::NAN,
+            -0.0,
+            -3.0,
+            0.0,
+            f64::INFINITY,
+            1.0,
+            -f64::NAN,
+            7.0,
+        ];
+        let mut order = FloatOrder {
+            nan: NanPolicy::First,
+            signed_zero: SignedZero::Equal,
+        };
+        let expected = crate::single_core_sort::merge_sort_floats(&test_vec, order).unwrap();
+        let bits =
+            |sorted: Vec<f64>| -> Vec<u64> { sorted.into_iter().map(f64::to_bits).collect() };
+        for sorted in [
+            merge_sort_parallel_floats(&test_vec, order),
+            merge_sort_parallel_limit_floats(&test_vec, 3, order),
+            merge_sort_threadpool_floats(&test_vec, 3, order),
+            merge_sort_threadpool_chunks_floats(&test_vec, 3, order),
+        ] {
+            assert_eq!(bits(sorted.unwrap()), bits(expected.clone()));
+        }
+        order.nan = NanPolicy::Error;
+        assert_eq!(
+            merge_sort_threadpool_floats(&test_vec, 3, order),
+            Err(NanError)
+        );
+    }
+
+    #[test]
+    fn hybrid_sort_is_stable() {
+        let test_vec: Vec<(usize, usize)> = scrambled(2000, 97).zip(0..).collect();
+        let mut expected = test_vec.clone();
+        expected.sort_by_key(|v| v.0);
+        for threads in [1, 2, 3, 8] {
+            assert_eq!(
+                merge_sort_hybrid_by_key(&test_vec, threads, |v| v.0),
+                expected,
+                "{threads} threads"
+            );
+        }
+        let keys: Vec<usize> = test_vec.iter().map(|v| v.0).collect();
+        let mut sorted_keys = keys.clone();
+        sorted_keys.sort();
+        for chunk_sort in [ChunkSort::default(), ChunkSort::Timsort] {
+            assert_eq!(
+                merge_sort_hybrid_with_chunk_sort(&keys, 4, chunk_sort),
+                sorted_keys
+            );
+        }
+        assert_eq!(merge_sort_hybrid(&[3, 1, 2], 8), vec![1, 2, 3]);
+        assert!(merge_sort_hybrid::<u32>(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn sort_with_stats() {
+        let test_vec: Vec<u32> = scrambled(1000, 1009).collect();
+        let (expected, expected_stats) = crate::single_core_sort::merge_sort_with_stats(&test_vec);
+        let mut all_stats = Vec::new();
+        for (sorted, stats) in [
+            merge_sort_parallel_with_stats(&test_vec),
+            merge_sort_threadpool_with_stats(&test_vec, 4),
+            merge_sort_threadpool_chunks_with_stats(&test_vec, 4),
+        ] {
+            assert_eq!(sorted, expected);
+            assert_eq!(stats.passes, 7);
+            assert_eq!(stats.pass_times.len(), 7);
+            assert_eq!(stats.moves, expected_stats.moves);
+            // The copy of the input and the buffer
+            assert_eq!(stats.allocations, 2);
+            all_stats.push(stats);
+        }
+        // One task per block or pair of bins, or at most one per thread,
+        // the last two passes split their merges between the 4 threads
+        assert_eq!(all_stats[1].dispatches, 63 + 32 + 16 + 8 + 4 + 4 + 4);
+        assert_eq!(all_stats[2].dispatches, 7 * 4);
+        // A single thread never splits a merge, so the merges are the sequential ones
+        for (_, stats) in [
+            merge_sort_threadpool_with_stats(&test_vec, 1),
+            merge_sort_threadpool_chunks_with_stats(&test_vec, 1),
+        ] {
+            assert_eq!(stats.comparisons, expected_stats.comparisons);
+        }
+    }
+
+    #[test]
+    fn split_merges_match_single_core() {
+        // Runs of equal keys straddle the cuts between the parts of the merges
+        let test_vec: Vec<(usize, usize)> = scrambled(1500, 23).zip(0..).collect();
+        let mut expected = test_vec.clone();
+        expected.sort_by_key(|v| v.0);
+        for threads in [2, 5, 8, 64] {
+            let sorted = merge_sort_threadpool_chunks_by_key(&test_vec, threads, |v| v.0);
+            assert_eq!(sorted, expected, "{threads} threads");
+            let sorted = merge_sort_threadpool_by_key(&test_vec, threads, |v| v.0);
+            assert_eq!(sorted, expected, "{threads} threads");
+        }
+        let bins = [1, 3, 3, 5, 2, 3, 3, 4];
+        for rank in 0..=8 {
+            // Equal values are taken from the first bin first
+            let taken_from_first = [0, 1, 1, 2, 3, 3, 3, 3, 4][rank];
+            assert_eq!(
+                co_rank(&bins[..4], &bins[4..], rank, &|a: &u32, b: &u32| a < b),
+                taken_from_first
+            );
+        }
+    }
+
+    #[test]
+    fn sort_move_only_values() {
+        let test_vec: Vec<Box<u32>> = scrambled(100, 31).map(Box::new).collect();
+        let mut expected: Vec<u32> = test_vec.iter().map(|v| **v).collect();
+        expected.sort();
+        let unbox = |sorted: Vec<Box<u32>>| sorted.into_iter().map(|v| *v).collect::<Vec<u32>>();
+        let boxed = || {
+            test_vec
+                .iter()
+                .map(|v| Box::new(**v))
+                .collect::<Vec<Box<u32>>>()
+        };
+        assert_eq!(unbox(merge_sort_parallel_vec(boxed())), expected);
+        assert_eq!(unbox(merge_sort_parallel_limit_vec(boxed(), 3)), expected);
+        assert_eq!(unbox(merge_sort_threadpool_vec(boxed(), 3)), expected);
+        assert_eq!(
+            unbox(merge_sort_threadpool_chunks_vec(boxed(), 3)),
+            expected
+        );
+    }
+
+    #[test]
+    fn sort_borrowed_values_in_place() {
+        let request = String::from("pear fig apple kiwi fig banana plum cherry date lime");
+        let mut expected: Vec<&str> = request.split(' ').collect();
+        expected.sort();
+        let sorts: [fn(&mut [&str]); 4] = [
+            |v| merge_sort_parallel_in_place(v),
+            |v| merge_sort_parallel_limit_in_place(v, 3),
+            |v| merge_sort_threadpool_in_place(v, 3),
+            |v| merge_sort_threadpool_chunks_in_place(v, 3),
+        ];
+        for sort in sorts {
+            let mut words: Vec<&str> = request.split(' ').collect();
+            sort(&mut words);
+            assert_eq!(words, expected);
+        }
+
+        // Equal keys keep the order of the words
+        let mut by_length: Vec<&str> = request.split(' ').collect();
+        by_length.sort_by_key(|w| w.len());
+        let mut words: Vec<&str> = request.split(' ').collect();
+        merge_sort_threadpool_chunks_in_place_by_key(&mut words, 3, |w| w.len());
+        assert_eq!(words, by_length);
+        let mut words: Vec<&str> = request.split(' ').collect();
+        merge_sort_parallel_limit_in_place_by(&mut words, 3, |a, b| b.len().cmp(&a.len()));
+        let mut expected: Vec<&str> = request.split(' ').collect();
+        expected.sort_by_key(|w| std::cmp::Reverse(w.len()));
+        assert_eq!(words, expected);
+    }
+
+    // Counts its drops across the threads
+    struct DropCounter {
+        key: u32,
+        drops: Arc<std::sync::atomic::AtomicUsize>,
+    }
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.drops
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn panic_at_random_comparisons() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+        let length = 200;
+        let mut seed = 0x2545_f491_u64;
+        for round in 0..30 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let panic_at = (seed % 1500) as usize;
+            let drops = Arc::new(AtomicUsize::new(0));
+            let test_vec: Vec<DropCounter> = scrambled(length, 61)
+                .map(|key| DropCounter {
+                    key,
+                    drops: Arc::clone(&drops),
+                })
+                .collect();
+            let comparisons = AtomicUsize::new(0);
+            let is_less = move |a: &DropCounter, b: &DropCounter| {
+                let count = comparisons.fetch_add(1, Relaxed);
+                assert!(count < panic_at, "injected panic");
+                a.key < b.key
+            };
+            let config = SortConfig::default();
+            // Few samples, so that the sample sort distributes the 200 values
+            let sample_config = SampleSortConfig {
+                oversampling: 2,
+                ..SampleSortConfig::default()
+            };
+            let result = panic::catch_unwind(AssertUnwindSafe(|| match round % 5 {
+                0 => merge_sort_parallel_impl(test_vec, config, is_less),
+                1 => merge_sort_parallel_limit_impl(test_vec, 3, config, is_less),
+                2 => merge_sort_threadpool_impl(test_vec, 3, config, is_less),
+                3 => merge_sort_threadpool_chunks_impl(test_vec, 3, config, is_less),
+                _ => {
+                    let mut values = test_vec;
+                    sample_sort_impl(&mut values, 3, sample_config, &is_less);
+                    values
+                }
+            }));
+            // Sorts that finish before the injected panic must still be correct
+            if let Ok(sorted) = result {
+                assert!(sorted.is_sorted_by_key(|v| v.key));
+                drop(sorted);
+            }
+            assert_eq!(drops.load(Relaxed), length);
+        }
+    }
+
+    #[test]
+    fn panic_keeps_a_permutation_in_place() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+        let input: Vec<(u32, usize)> = scrambled(300, 61).zip(0..).collect();
+        let mut expected = input.clone();
+        expected.sort();
+        for panic_at in [0, 10, 200, 700, 1500] {
+            for round in 0..2 {
+                let mut values = input.clone();
+                let comparisons = AtomicUsize::new(0);
+                let compare = |a: &(u32, usize), b: &(u32, usize)| {
+                    let count = comparisons.fetch_add(1, Relaxed);
+                    assert!(count < panic_at, "injected panic");
+                    a.0.cmp(&b.0)
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| match round {
+                    0 => merge_sort_parallel_in_place_by(&mut values, compare),
+                    _ => merge_sort_threadpool_in_place_by(&mut values, 3, compare),
+                }));
+                assert!(result.is_err(), "panic at {panic_at}");
+                values.sort();
+                assert_eq!(values, expected, "panic at {panic_at}");
+            }
+        }
+    }
 }
@@ -0,0 +1,277 @@
+use std::cmp::Ordering;
+use std::mem::MaybeUninit;
+use std::ptr;
+
+use super::threadpool::ThreadPool;
+use super::{LessFn, SortByTraits, SortTraits};
+use crate::single_core_sort::{SortConfig, merge_sort_impl};
+
+// More buckets than threads, so that the pool balances the larger buckets
+const BUCKETS_PER_THREAD: usize = 4;
+
+/// Configuration of the sample sorts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleSortConfig {
+    /// Values sampled for each bucket, the splitters between the buckets are
+    /// picked among them. More samples give buckets of closer sizes.
+    pub oversampling: usize,
+    /// Keeps equal elements in their original order by sorting the buckets with
+    /// the merge sort, the standard library's unstable sort is used otherwise.
+    pub stable: bool,
+}
+
+impl Default for SampleSortConfig {
+    fn default() -> Self {
+        SampleSortConfig {
+            oversampling: 16,
+            stable: true,
+        }
+    }
+}
+
+/// Distributes the values into buckets bounded by splitters sampled from the
+/// input, then sorts the buckets in parallel with the single-core merge sort.
+/// The values are only moved twice before the buckets are sorted, which suits
+/// very large inputs better than the merge passes over the whole input.
+pub fn sample_sort<T: SortTraits + Clone>(input: &[T], threads: usize) -> Vec<T> {
+    sample_sort_vec(input.to_vec(), threads)
+}
+
+pub fn sample_sort_vec<T: SortTraits>(mut input: Vec<T>, threads: usize) -> Vec<T> {
+    sample_sort_in_place(&mut input, threads);
+    input
+}
+
+pub fn sample_sort_with_config<T: SortTraits + Clone>(
+    input: &[T],
+    threads: usize,
+    config: SampleSortConfig,
+) -> Vec<T> {
+    let mut values = input.to_vec();
+    sample_sort_impl(&mut values, threads, config, &|a: &T, b: &T| a < b);
+    values
+}
+
+pub fn sample_sort_by<T, F>(input: &[T], threads: usize, compare: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    let mut values = input.to_vec();
+    sample_sort_in_place_by(&mut values, threads, compare);
+    values
+}
+
+pub fn sample_sort_by_key<T, K, F>(input: &[T], threads: usize, f: F) -> Vec<T>
+where
+    T: SortByTraits + Clone,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    let mut values = input.to_vec();
+    sample_sort_in_place_by_key(&mut values, threads, f);
+    values
+}
+
+pub fn sample_sort_in_place<T: SortTraits>(values: &mut [T], threads: usize) {
+    sample_sort_impl(
+        values,
+        threads,
+        SampleSortConfig::default(),
+        &|a: &T, b: &T| a < b,
+    )
+}
+
+pub fn sample_sort_in_place_by<T, F>(values: &mut [T], threads: usize, compare: F)
+where
+    T: SortByTraits,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    sample_sort_impl(
+        values,
+        threads,
+        SampleSortConfig::default(),
+        &|a: &T, b: &T| compare(a, b) == Ordering::Less,
+    )
+}
+
+pub fn sample_sort_in_place_by_key<T, K, F>(values: &mut [T], threads: usize, f: F)
+where
+    T: SortByTraits,
+    K: Ord,
+    F: Fn(&T) -> K + Sync,
+{
+    sample_sort_impl(
+        values,
+        threads,
+        SampleSortConfig::default(),
+        &|a: &T, b: &T| f(a) < f(b),
+    )
+}
+
+pub(super) fn sample_sort_impl<T: SortByTraits, F: LessFn<T>>(
+    values: &mut [T],
+    threads: usize,
+    config: SampleSortConfig,
+    is_less: &F,
+) {
+    let length = values.len();
+    let threads = threads.max(1);
+    let samples = threads * BUCKETS_PER_THREAD * config.oversampling.max(1);
+    // Too small to be worth the distribution
+    if threads == 1 || length < 2 * samples {
+        sort_bucket(values, config, is_less);
+        return;
+    }
+    let pool = ThreadPool::new(threads);
+    let mut buffer = Box::new_uninit_slice(length);
+    let bucket_sizes = distribute(values, &mut buffer, &pool, samples, config, is_less);
+    // SAFETY: the buffer holds a copy of every value, the values are overwritten
+    // without being dropped. Nothing can panic between the copies and the sorts,
+    // which keep the values holding a permutation of the input.
+    unsafe {
+        ptr::copy_nonoverlapping(buffer.as_ptr().cast::<T>(), values.as_mut_ptr(), length);
+    }
+    let mut rest = values;
+    pool.scoped(|scope| {
+        for size in bucket_sizes {
+            let (bucket, next) = rest.split_at_mut(size);
+            rest = next;
+            scope.execute(move || sort_bucket(bucket, config, is_less));
+        }
+    });
+}
+
+// Copies the values into their bucket in the buffer, keeping their order within
+// each bucket. Returns the sizes of the buckets.
+fn distribute<T: SortByTraits, F: LessFn<T>>(
+    values: &[T],
+    buffer: &mut [MaybeUninit<T>],
+    pool: &ThreadPool,
+    samples: usize,
+    config: SampleSortConfig,
+    is_less: &F,
+) -> Vec<usize> {
+    let splitters = pick_splitters(values, samples, config.oversampling.max(1), is_less);
+    let buckets = splitters.len() + 1;
+
+    // Positions of the values of each bucket, for each chunk of the values
+    let chunk_size = values.len().div_ceil(pool.size());
+    let mut positions: Vec<Vec<Vec<usize>>> = vec![Vec::new(); values.len().div_ceil(chunk_size)];
+    let splitters = &splitters;
+    pool.scoped(|scope| {
+        for (id, chunk_positions) in positions.iter_mut().enumerate() {
+            let start = id * chunk_size;
+            let chunk = &values[start..values.len().min(start + chunk_size)];
+            scope.execute(move || {
+                *chunk_positions = vec![Vec::new(); buckets];
+                for (position, value) in (start..).zip(chunk) {
+                    // Equal values always land in the same bucket
+                    let bucket = splitters.partition_point(|splitter| !is_less(value, splitter));
+                    chunk_positions[bucket].push(position);
+                }
+            });
+        }
+    });
+
+    let bucket_sizes: Vec<usize> = (0..buckets)
+        .map(|bucket| positions.iter().map(|chunk| chunk[bucket].len()).sum())
+        .collect();
+    let positions = &positions;
+    let mut rest = buffer;
+    pool.scoped(|scope| {
+        for (bucket, &size) in bucket_sizes.iter().enumerate() {
+            let (destination, next) = rest.split_at_mut(size);
+            rest = next;
+            scope.execute(move || {
+                let sources = positions.iter().flat_map(|chunk| &chunk[bucket]);
+                for (slot, &position) in destination.iter_mut().zip(sources) {
+                    // SAFETY: each value is copied once, the caller makes sure
+                    // that only one of the two copies gets dropped
+                    slot.write(unsafe { ptr::read(&values[position]) });
+                }
+            });
+        }
+    });
+    bucket_sizes
+}
+
+// Sorts a sample of the values, spread over the input by a fixed pseudo-random
+// sequence, and keeps one value every `oversampling` as splitter
+fn pick_splitters<'a, T, F: LessFn<T>>(
+    values: &'a [T],
+    samples: usize,
+    oversampling: usize,
+    is_less: &F,
+) -> Vec<&'a T> {
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut sample: Vec<&T> = (0..samples)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            &values[(seed % values.len() as u64) as usize]
+        })
+        .collect();
+    merge_sort_impl(&mut sample, SortConfig::default(), &mut |a: &&T, b: &&T| {
+        is_less(a, b)
+    });
+    let mut splitters: Vec<&T> = sample
+        .into_iter()
+        .skip(oversampling)
+        .step_by(oversampling)
+        .collect();
+    // Equal splitters would only bound empty buckets
+    splitters.dedup_by(|next, last| !is_less(last, next));
+    splitters
+}
+
+fn sort_bucket<T, F: LessFn<T>>(bucket: &mut [T], config: SampleSortConfig, is_less: &F) {
+    if config.stable {
+        merge_sort_impl(bucket, SortConfig::default(), &mut |a: &T, b: &T| {
+            is_less(a, b)
+        });
+    } else {
+        bucket.sort_unstable_by(|a, b| {
+            if is_less(a, b) {
+                Ordering::Less
+            } else if is_less(b, a) {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn sample_sort_is_stable() {
+        let test_vec: Vec<(usize, usize)> = scrambled(5000, 211).zip(0..).collect();
+        let mut expected = test_vec.clone();
+        expected.sort_by_key(|v| v.0);
+        for threads in [1, 2, 3, 8] {
+            assert_eq!(
+                sample_sort_by_key(&test_vec, threads, |v| v.0),
+                expected,
+                "{threads} threads"
+            );
+        }
+        // Few distinct values, most splitters are equal
+        let keys: Vec<u32> = scrambled(5000, 3).collect();
+        let mut sorted_keys = keys.clone();
+        sorted_keys.sort();
+        assert_eq!(sample_sort(&keys, 4), sorted_keys);
+        let unstable = SampleSortConfig {
+            stable: false,
+            ..SampleSortConfig::default()
+        };
+        assert_eq!(sample_sort_with_config(&keys, 4, unstable), sorted_keys);
+        assert_eq!(sample_sort(&[3, 1, 2], 8), vec![1, 2, 3]);
+        assert!(sample_sort::<u32>(&[], 4).is_empty());
+    }
+}
@@ -1,4 +1,8 @@
 use std::{
+    cell::Cell,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
     thread,
     sync::{Arc, Mutex},
     sync::mpsc,
@@ -17,7 +21,10 @@ pub struct ThreadPool {
 }
 
 impl ThreadPool {
+    // A pool without workers would drop the receiver and fail to run any job,
+    // so at least one worker is started
     pub fn new(n: usize) -> ThreadPool {
+        let n = n.max(1);
         let mut workers = Vec::with_capacity(n);
         let (sender, receiver) = mpsc::channel();
         let rc = Arc::new(Mutex::new(receiver));
@@ -33,9 +40,56 @@ impl ThreadPool {
         }
     }
 
-    pub fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
-        let job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Runs `f`, which sends jobs borrowing data from outside the call to the pool.
+    // Returns once every job has finished, then propagates the first panic of the jobs,
+    // so that no job still uses the data when it is dropped.
+    pub fn scoped<'scope, R>(&self, f: impl FnOnce(&Scope<'_, 'scope>) -> R) -> R {
+        let (done_write, done_read) = mpsc::channel();
+        let scope = Scope {
+            pool: self,
+            done: done_write,
+            jobs: Cell::new(0),
+            _borrows: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        let mut first_panic = None;
+        for _ in 0..scope.jobs.get() {
+            if let Err(payload) = done_read.recv().unwrap() {
+                first_panic.get_or_insert(payload);
+            }
+        }
+        match (result, first_panic) {
+            (Err(payload), _) | (Ok(_), Some(payload)) => panic::resume_unwind(payload),
+            (Ok(result), None) => result,
+        }
+    }
+}
+
+pub struct Scope<'pool, 'scope> {
+    pool: &'pool ThreadPool,
+    done: mpsc::Sender<thread::Result<()>>,
+    jobs: Cell<usize>,
+    // Invariant in 'scope, like `std::thread::Scope`
+    _borrows: PhantomData<&'scope mut &'scope ()>,
+}
+
+impl<'scope> Scope<'_, 'scope> {
+    pub fn execute<F: FnOnce() + Send + 'scope>(&self, f: F) {
+        let done = self.done.clone();
+        // The job catches its panic so that the worker survives it
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            done.send(result).unwrap();
+        });
+        // SAFETY: `ThreadPool::scoped` waits for every job before returning,
+        // and the data borrowed for 'scope outlives that call
+        let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.sender.as_ref().unwrap().send(job).unwrap();
+        self.jobs.set(self.jobs.get() + 1);
     }
 }
 
@@ -1,42 +1,146 @@
+//! Sequential merge sorts.
+//!
+//! If a comparison panics, the slice being sorted still holds a permutation of its
+//! original elements: none of them is duplicated, lost or dropped twice.
+//!
+//! The module works without the standard library, the functions allocating
+//! their buffer or their output need the `alloc` feature.
+
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec::Vec};
+use core::cmp::Ordering;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+#[cfg(feature = "alloc")]
+mod argsort;
+mod block;
+#[cfg(feature = "alloc")]
+mod columns;
+mod comparator;
+#[cfg(feature = "alloc")]
+mod dedup;
+mod float;
+mod merge;
+mod merge_iter;
+#[cfg(feature = "alloc")]
+mod natural;
+mod rotation;
+mod set_ops;
+mod small_sort;
+mod stats;
+#[cfg(feature = "alloc")]
+mod timsort;
+#[cfg(feature = "alloc")]
+use argsort::permute_unchecked;
+#[cfg(feature = "alloc")]
+pub use argsort::{apply_permutation, merge_argsort, merge_argsort_by, merge_argsort_by_key};
+pub use block::{block_merge_sort_in_place, block_merge_sort_in_place_by};
+#[cfg(feature = "alloc")]
+pub use columns::{Column, KeyColumn, sort_columns};
+#[cfg(feature = "std")]
+pub(crate) use columns::{columns_length, compare_rows};
+pub use comparator::{ThenBy, by, by_desc};
+#[cfg(feature = "alloc")]
+pub use dedup::{
+    Keep, group_by_sorted, merge_sort_dedup, merge_sort_dedup_by, merge_sort_dedup_by_key,
+};
+pub use float::{FloatOrder, NanError, NanPolicy, SignedZero, SortFloat};
+#[cfg(feature = "alloc")]
+pub use float::{merge_sort_floats, merge_sort_floats_in_place};
+#[cfg(feature = "alloc")]
+pub use merge::{merge, merge_by, merge_many, merge_many_by};
+pub use merge::{merge_into, merge_into_by};
+#[cfg(feature = "alloc")]
+pub use merge_iter::{KMergeIter, kmerge_iter, kmerge_iter_by};
+pub use merge_iter::{MergeIter, merge_iter, merge_iter_by};
+#[cfg(feature = "alloc")]
+pub use natural::{
+    merge_sort_natural, merge_sort_natural_in_place, merge_sort_natural_in_place_by,
+};
+pub use rotation::{rotation_merge_sort_in_place, rotation_merge_sort_in_place_by};
+#[cfg(feature = "std")]
+pub(crate) use set_ops::set_op_impl;
+pub use set_ops::{Semantics, SetOp, SetOpIter, set_op_iter, set_op_iter_by};
+#[cfg(feature = "alloc")]
+pub use set_ops::{set_op, set_op_by};
+pub use small_sort::{Scratch, SmallSort, SortConfig};
+pub(crate) use stats::PassObserver;
+pub use stats::SortStats;
+#[cfg(feature = "alloc")]
+pub(crate) use stats::StatsRecorder;
+#[cfg(feature = "std")]
+pub(crate) use timsort::timsort_impl;
+#[cfg(feature = "alloc")]
+pub use timsort::{timsort, timsort_in_place, timsort_in_place_by};
+
 // Trait aliasing for readibility
 // https://stackoverflow.com/questions/26070559/is-there-any-way-to-create-a-type-alias-for-multiple-trai  ts
-pub trait SortTraits: Clone + PartialOrd {}
-impl<T: Clone + PartialOrd> SortTraits for T {}
-struct SortVecPair<T: SortTraits> {
+// Elements are moved rather than cloned, `Clone` is only needed to sort a copy of a slice
+pub trait SortTraits: PartialOrd {}
+impl<T: PartialOrd> SortTraits for T {}
+// Each element lives in either the values or the buffer at any time, so that a panic
+// in a comparison leaves the values holding a permutation of the input
+pub(crate) struct SortVecPair<'a, T> {
     bin_size: usize,
-    length: usize,
-    values: Vec<T>,
-    buffer: Vec<T>,
+    pub(crate) length: usize,
+    values: &'a mut [T],
+    buffer: &'a mut [MaybeUninit<T>],
+    // The values and the buffer swap roles at each merge pass,
+    // this tells which one holds the partially sorted data
+    pub(crate) sorted_in_buffer: bool,
 }
 struct BinsPositions {
     start: usize,
     mid: usize,
     end: usize,
 }
-impl<T: SortTraits> SortVecPair<T> {
-    fn new(unsorted_vec: &[T]) -> SortVecPair<T> {
+impl<'a, T> SortVecPair<'a, T> {
+    // The values are made of sorted bins of `bin_size` elements,
+    // the buffer must be at least as long as the values
+    pub(crate) fn new(
+        unsorted_vec: &'a mut [T],
+        buffer: &'a mut [MaybeUninit<T>],
+        bin_size: usize,
+    ) -> SortVecPair<'a, T> {
         SortVecPair {
-            bin_size: 1,
+            bin_size,
             length: unsorted_vec.len(),
-            values: unsorted_vec.to_vec(),
-            buffer: unsorted_vec.to_vec(),
+            buffer: &mut buffer[..unsorted_vec.len()],
+            values: unsorted_vec,
+            sorted_in_buffer: false,
+        }
+    }
+
+    // Bins are read from the source and merged into the destination.
+    // The source holds every element, what the destination holds is overwritten
+    // without being dropped.
+    pub(crate) fn get_source_and_destination(&mut self) -> (&[T], &mut [MaybeUninit<T>]) {
+        if self.sorted_in_buffer {
+            // SAFETY: the buffer was filled by the previous pass,
+            // and the values in the destination are copies of the ones in the buffer
+            unsafe {
+                (
+                    &*(self.buffer as *const [MaybeUninit<T>] as *const [T]),
+                    &mut *(self.values as *mut [T] as *mut [MaybeUninit<T>]),
+                )
+            }
+        } else {
+            (self.values, self.buffer)
         }
     }
 
-    fn finish_merge(&mut self) {
-        // Reinject the buffer in the values
-        self.values = self.buffer.clone();
+    pub(crate) fn finish_merge(&mut self) {
+        // The destination of this pass is the source of the next one
+        self.sorted_in_buffer = !self.sorted_in_buffer;
         // Double the bin size to prepare for the next merging iteration
         self.bin_size *= 2;
     }
 
-    fn get_bin_size(&self) -> usize {
+    pub(crate) fn get_bin_size(&self) -> usize {
         self.bin_size
     }
 
-    fn get_values(self) -> Vec<T> {
-        self.values
-    }
     fn get_bins_positions(&self, end_prev: usize) -> Option<BinsPositions> {
         if end_prev + 2 * self.bin_size < self.length {
             Some(BinsPositions {
@@ -50,52 +154,408 @@ impl<T: SortTraits> SortVecPair<T> {
                 mid: end_prev + self.bin_size,
                 end: self.length,
             })
+        } else if end_prev < self.length {
+            // Lone last bin, still has to reach the destination
+            Some(BinsPositions {
+                start: end_prev,
+                mid: self.length,
+                end: self.length,
+            })
         } else {
             None
         }
     }
 }
-pub fn merge_sort<T: SortTraits>(input: &[T]) -> Vec<T> {
-    let mut sort_vec_pair = SortVecPair::new(input);
-    while sort_vec_pair.get_bin_size() < input.len() {
+
+impl<T> Drop for SortVecPair<'_, T> {
+    // Also runs when a comparison panics
+    fn drop(&mut self) {
+        // Only needed after an odd number of passes
+        if self.sorted_in_buffer {
+            // SAFETY: the buffer holds every element, the values only hold copies
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    self.buffer.as_ptr().cast::<T>(),
+                    self.values.as_mut_ptr(),
+                    self.length,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort<T: SortTraits + Clone>(input: &[T]) -> Vec<T> {
+    let mut values = input.to_vec();
+    merge_sort_in_place(&mut values);
+    values
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_with_config<T: SortTraits + Clone>(input: &[T], config: SortConfig) -> Vec<T> {
+    let mut values = input.to_vec();
+    merge_sort_in_place_with_config(&mut values, config);
+    values
+}
+
+/// Sorts an owned vector without copying it first, the elements do not need to be `Clone`.
+#[cfg(feature = "alloc")]
+pub fn merge_sort_vec<T: SortTraits>(mut values: Vec<T>) -> Vec<T> {
+    merge_sort_in_place(&mut values);
+    values
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_by<T, F>(input: &[T], compare: F) -> Vec<T>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    let mut values = input.to_vec();
+    merge_sort_in_place_by(&mut values, compare);
+    values
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_by_key<T, K, F>(input: &[T], f: F) -> Vec<T>
+where
+    T: Clone,
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    let mut values = input.to_vec();
+    merge_sort_in_place_by_key(&mut values, f);
+    values
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_by_cached_key<T, K, F>(input: &[T], f: F) -> Vec<T>
+where
+    T: Clone,
+    K: Clone + Ord,
+    F: FnMut(&T) -> K,
+{
+    let mut values = input.to_vec();
+    merge_sort_in_place_by_cached_key(&mut values, f);
+    values
+}
+
+/// Sorts the slice in the caller's storage, using a single scratch buffer.
+/// Equal elements keep their relative order.
+#[cfg(feature = "alloc")]
+pub fn merge_sort_in_place<T: SortTraits>(values: &mut [T]) {
+    merge_sort_in_place_with_config(values, SortConfig::default());
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_in_place_with_config<T: SortTraits>(values: &mut [T], config: SortConfig) {
+    merge_sort_impl(values, config, &mut |a: &T, b: &T| a < b);
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_in_place_by<T, F>(values: &mut [T], mut compare: F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    merge_sort_impl(values, SortConfig::default(), &mut |a: &T, b: &T| {
+        compare(a, b) == Ordering::Less
+    });
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_in_place_by_key<T, K, F>(values: &mut [T], mut f: F)
+where
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    merge_sort_impl(values, SortConfig::default(), &mut |a: &T, b: &T| {
+        f(a) < f(b)
+    });
+}
+
+/// Computes the key of every element once, then sorts the keys and
+/// moves the values to their sorted positions.
+#[cfg(feature = "alloc")]
+pub fn merge_sort_in_place_by_cached_key<T, K, F>(values: &mut [T], f: F)
+where
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    let mut indices: Vec<(K, usize)> = values.iter().map(f).zip(0..).collect();
+    // The index breaks no ties, the merge sort is already stable
+    let key_is_less = &mut |a: &(K, usize), b: &(K, usize)| a.0 < b.0;
+    merge_sort_impl(&mut indices, SortConfig::default(), key_is_less);
+    let mut permutation: Vec<usize> = indices.into_iter().map(|(_, id)| id).collect();
+    permute_unchecked(values, &mut permutation);
+}
+
+/// Number of pairs of values out of order: `i < j` with `values[j] < values[i]`.
+/// Sorts the indices of the values, which are left untouched.
+#[cfg(feature = "alloc")]
+pub fn count_inversions<T: SortTraits>(values: &[T]) -> u64 {
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    inversions_impl(&mut indices, &mut |a: &usize, b: &usize| {
+        values[*a] < values[*b]
+    })
+}
+
+/// Sorted copy of the input, along with the number of inversions of the input.
+#[cfg(feature = "alloc")]
+pub fn merge_sort_with_inversions<T: SortTraits + Clone>(input: &[T]) -> (Vec<T>, u64) {
+    let mut values = input.to_vec();
+    let inversions = merge_sort_in_place_with_inversions(&mut values);
+    (values, inversions)
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_in_place_with_inversions<T: SortTraits>(values: &mut [T]) -> u64 {
+    inversions_impl(values, &mut |a: &T, b: &T| a < b)
+}
+
+// Merges from single values, so that every inversion is counted by a merge
+#[cfg(feature = "alloc")]
+fn inversions_impl<T, F: FnMut(&T, &T) -> bool>(values: &mut [T], is_less: &mut F) -> u64 {
+    let mut buffer = Box::new_uninit_slice(values.len());
+    merge_passes(
+        &mut SortVecPair::new(values, &mut buffer, 1),
+        is_less,
+        &mut (),
+    )
+}
+
+/// Sorts the slice with the caller's buffer, without allocating.
+/// A buffer as long as the values gives the regular merge sort, a shorter one,
+/// even empty, merges with rotations like `rotation_merge_sort_in_place`.
+pub fn merge_sort_with_buffer<T: SortTraits>(values: &mut [T], buffer: &mut [MaybeUninit<T>]) {
+    sort_with_buffer(
+        values,
+        buffer,
+        SortConfig::default(),
+        &mut |a: &T, b: &T| a < b,
+        &mut (),
+    );
+}
+
+pub fn merge_sort_with_buffer_by<T, F>(
+    values: &mut [T],
+    buffer: &mut [MaybeUninit<T>],
+    mut compare: F,
+) where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    sort_with_buffer(
+        values,
+        buffer,
+        SortConfig::default(),
+        &mut |a: &T, b: &T| compare(a, b) == Ordering::Less,
+        &mut (),
+    );
+}
+
+/// Same as `merge_sort`, along with what the sort did.
+#[cfg(feature = "alloc")]
+pub fn merge_sort_with_stats<T: SortTraits + Clone>(input: &[T]) -> (Vec<T>, SortStats) {
+    let mut values = input.to_vec();
+    let mut recorder = StatsRecorder::default();
+    recorder.allocated(usize::from(!values.is_empty()));
+    let mut comparisons = 0;
+    merge_sort_observed(
+        &mut values,
+        SortConfig::default(),
+        &mut |a: &T, b: &T| {
+            comparisons += 1;
+            a < b
+        },
+        &mut recorder,
+    );
+    recorder.stats.comparisons = comparisons;
+    (values, recorder.stats)
+}
+
+// Allocates the scratch space allowed by the configuration
+#[cfg(feature = "alloc")]
+pub(crate) fn merge_sort_impl<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    config: SortConfig,
+    is_less: &mut F,
+) {
+    merge_sort_observed(values, config, is_less, &mut ());
+}
+
+#[cfg(feature = "alloc")]
+fn merge_sort_observed<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    config: SortConfig,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) {
+    let length = values.len();
+    let buffer_length = if config.block_size() >= length {
+        0
+    } else {
+        match config.scratch {
+            Scratch::Full => length,
+            Scratch::Sqrt => length.isqrt(),
+            Scratch::Constant => 0,
+        }
+    };
+    let mut buffer = Box::new_uninit_slice(buffer_length);
+    observer.allocated(usize::from(buffer_length > 0));
+    sort_with_buffer(values, &mut buffer, config, is_less, observer);
+}
+
+// Bottom-up merge sort, `is_less` is the strict ordering of the elements
+fn sort_with_buffer<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    buffer: &mut [MaybeUninit<T>],
+    config: SortConfig,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) {
+    let length = values.len();
+    let block_size = config.block_size();
+    if block_size < length && buffer.len() < length {
+        block::block_merge_sort_impl(values, buffer, config, is_less, observer);
+        return;
+    }
+    sort_blocks(values, config, is_less, observer);
+    if block_size >= length {
+        return;
+    }
+    let mut sort_vec_pair = SortVecPair::new(values, buffer, block_size);
+    merge_passes(&mut sort_vec_pair, is_less, observer);
+    // Dropping the pair moves the values back from the buffer if needed
+    if sort_vec_pair.sorted_in_buffer {
+        observer.moved(length);
+    }
+}
+
+// Small blocks are sorted in place, the merge passes start from them
+fn sort_blocks<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    config: SortConfig,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) {
+    let block_size = config.block_size();
+    if block_size > 1 && values.len() > 1 {
+        observer.start_pass();
+        for block in values.chunks_mut(block_size) {
+            config.sort_block(block, is_less);
+        }
+        observer.finish_pass();
+    }
+}
+
+// Merges the bins until they cover the values, returns the number of inversions
+// between the bins that were merged
+fn merge_passes<T, F: FnMut(&T, &T) -> bool>(
+    sort_vec_pair: &mut SortVecPair<'_, T>,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) -> u64 {
+    let mut inversions = 0;
+    while sort_vec_pair.get_bin_size() < sort_vec_pair.length {
+        observer.start_pass();
         let mut end_prev = 0;
         while let Some(BinsPositions { start, mid, end }) =
             sort_vec_pair.get_bins_positions(end_prev)
         {
-            let bin1 = &sort_vec_pair.values[start..mid];
-            let bin2 = &sort_vec_pair.values[mid..end];
-            let buf = &mut sort_vec_pair.buffer[start..end];
-            merge_bins(bin1, bin2, buf);
+            let (source, destination) = sort_vec_pair.get_source_and_destination();
+            let bin1 = &source[start..mid];
+            let bin2 = &source[mid..end];
+            let buf = &mut destination[start..end];
+            // SAFETY: every element of the source is copied once to the destination
+            // and the roles are swapped at the end of the pass
+            inversions += unsafe { merge_bins(bin1, bin2, buf, is_less) };
             end_prev = end;
         }
-        // Put merged bins from the buffer into the values
+        // Swap the roles of the values and the buffer
         // and increase the bins size.
         // Separate from the main operation
         // to ease threading.
         sort_vec_pair.finish_merge();
+        observer.moved(sort_vec_pair.length);
+        observer.finish_pass();
     }
-    sort_vec_pair.get_values()
+    inversions
 }
-fn merge_bins<T: SortTraits>(bin1: &[T], bin2: &[T], buf: &mut [T]) {
+
+// Takes from the second bin only when its value is strictly smaller,
+// so that equal values keep their original order.
+// The values are copied bitwise into the buffer, leaving the bins untouched
+// if a comparison panics.
+// Returns the number of inversions between the bins: each value taken from the second
+// bin is smaller than all the values left in the first one.
+// SAFETY: the caller must make sure that only one of the two copies gets dropped.
+pub(crate) unsafe fn merge_bins<T, F: FnMut(&T, &T) -> bool>(
+    bin1: &[T],
+    bin2: &[T],
+    buf: &mut [MaybeUninit<T>],
+    is_less: &mut F,
+) -> u64 {
+    debug_assert_eq!(bin1.len() + bin2.len(), buf.len());
     let mut id1 = 0;
     let mut id2 = 0;
-    for min_val in buf {
-        if id1 >= bin1.len() {
-            *min_val = bin2[id2].clone();
+    let mut id_buf = 0;
+    let mut inversions = 0;
+    while id1 < bin1.len() && id2 < bin2.len() {
+        let min_val = if is_less(&bin2[id2], &bin1[id1]) {
+            inversions += (bin1.len() - id1) as u64;
             id2 += 1;
-        }else if id2 >= bin2.len() {
-            *min_val = bin1[id1].clone();
-            id1 += 1;
+            &bin2[id2 - 1]
         } else {
-            let val1 = bin1[id1].clone();
-            let val2 = bin2[id2].clone();
-            if val1 <= val2 {
-                *min_val = val1;
-                id1 += 1;
-            } else {
-                *min_val = val2;
-                id2 += 1;
-            }
+            id1 += 1;
+            &bin1[id1 - 1]
+        };
+        buf[id_buf].write(unsafe { ptr::read(min_val) });
+        id_buf += 1;
+    }
+    // One of the bins is empty, the rest of the other one is copied as is
+    let (rest1, rest2) = (&bin1[id1..], &bin2[id2..]);
+    unsafe {
+        let dest = buf.as_mut_ptr().cast::<T>().add(id_buf);
+        ptr::copy_nonoverlapping(rest1.as_ptr(), dest, rest1.len());
+        ptr::copy_nonoverlapping(rest2.as_ptr(), dest.add(rest1.len()), rest2.len());
+    }
+    inversions
+}
+
+// Values of the left run moved to a buffer during a merge, and where they go
+// in the values. Dropping the hole moves the values left in the buffer.
+struct MergeHole<T> {
+    buffer: *const T,
+    start: usize,
+    end: usize,
+    dest: *mut T,
+}
+
+impl<T> MergeHole<T> {
+    unsafe fn take_left(&mut self, count: usize) {
+        unsafe {
+            ptr::copy_nonoverlapping(self.buffer.add(self.start), self.dest, count);
+            self.dest = self.dest.add(count);
+        }
+        self.start += count;
+    }
+
+    // The right values are after the destination and may overlap it
+    unsafe fn take_right(&mut self, right: *const T, count: usize) {
+        unsafe {
+            ptr::copy(right, self.dest, count);
+            self.dest = self.dest.add(count);
+        }
+    }
+}
+
+impl<T> Drop for MergeHole<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.buffer.add(self.start),
+                self.dest,
+                self.end - self.start,
+            );
         }
     }
 }
@@ -103,25 +563,29 @@ fn merge_bins<T: SortTraits>(bin1: &[T], bin2: &[T], buf: &mut [T]) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::scrambled;
 
     #[test]
     fn merge_bins_test() {
         let vec1 = [4, 5, 6];
         let vec2 = [1, 2, 3];
-        let mut vec3 = vec![0; 4];
+        let mut vec3 = [MaybeUninit::uninit(); 4];
         let bin1 = &vec1[..2];
         let bin2 = &vec2[1..];
         let buf = &mut vec3[..];
-        merge_bins(bin1, bin2, buf);
+        unsafe { merge_bins(bin1, bin2, buf, &mut |a: &i32, b: &i32| a < b) };
+        let vec3: Vec<i32> = vec3.iter().map(|v| unsafe { v.assume_init() }).collect();
         assert_eq!(vec3, vec![2, 3, 4, 5]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn sort_small_vec() {
         let test_vec = vec![15, 53, 1, 24, 3];
         assert_eq!(merge_sort(&test_vec), vec![1, 3, 15, 24, 53]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn sort_small_vec_float() {
         let test_vec = vec![15.1, 15.3, 53.2, 1.9, 1.5, 24.7, 3.2];
@@ -130,4 +594,292 @@ mod tests {
             vec![1.5, 1.9, 3.2, 15.1, 15.3, 24.7, 53.2]
         );
     }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_small_vec_in_place() {
+        let mut test_vec = vec![15, 53, 1, 24, 3, 1765, 22, 2, 8, 7, 4];
+        merge_sort_in_place(&mut test_vec);
+        assert_eq!(test_vec, vec![1, 2, 3, 4, 7, 8, 15, 22, 24, 53, 1765]);
+        assert_eq!(merge_sort_vec(vec![3, 2, 1]), vec![1, 2, 3]);
+    }
+
+    // Compares on the key only, the tag keeps track of the original order
+    #[cfg(feature = "alloc")]
+    #[derive(Clone, Debug, PartialEq)]
+    struct Keyed {
+        key: i32,
+        tag: char,
+    }
+    #[cfg(feature = "alloc")]
+    impl PartialOrd for Keyed {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            self.key.partial_cmp(&other.key)
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_in_place_is_stable() {
+        let mut test_vec: Vec<Keyed> = [(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (0, 'e')]
+            .into_iter()
+            .map(|(key, tag)| Keyed { key, tag })
+            .collect();
+        merge_sort_in_place(&mut test_vec);
+        let tags: String = test_vec.iter().map(|k| k.tag).collect();
+        assert_eq!(tags, "ebdac");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_by_comparator() {
+        let test_vec = vec![15, 53, 1, 24, 3];
+        assert_eq!(
+            merge_sort_by(&test_vec, |a, b| b.cmp(a)),
+            vec![53, 24, 15, 3, 1]
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_by_key_is_stable() {
+        let test_vec = vec![(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (0, 'e')];
+        let expected = vec![(0, 'e'), (1, 'b'), (1, 'd'), (2, 'a'), (2, 'c')];
+        assert_eq!(merge_sort_by_key(&test_vec, |v| v.0), expected);
+        assert_eq!(merge_sort_by_cached_key(&test_vec, |v| v.0), expected);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_by_cached_key_calls_key_once() {
+        let mut test_vec = vec!["ccc", "a", "bb", "dddd", "", "ee"];
+        let mut calls = 0;
+        merge_sort_in_place_by_cached_key(&mut test_vec, |s| {
+            calls += 1;
+            s.len()
+        });
+        assert_eq!(calls, 6);
+        assert_eq!(test_vec, vec!["", "a", "bb", "ee", "ccc", "dddd"]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_odd_and_even_pass_counts() {
+        // Covers lone last bins and results left in the buffer
+        for length in 0..70 {
+            let test_vec: Vec<u32> = scrambled(length, 31).collect();
+            let mut expected = test_vec.clone();
+            expected.sort();
+            assert_eq!(merge_sort(&test_vec), expected, "length {length}");
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn count_inversions_test() {
+        for length in [0, 1, 2, 17, 100, 257] {
+            let test_vec: Vec<u32> = scrambled(length, 29).collect();
+            let mut expected = 0;
+            for i in 0..test_vec.len() {
+                for j in i + 1..test_vec.len() {
+                    expected += u64::from(test_vec[j] < test_vec[i]);
+                }
+            }
+            assert_eq!(count_inversions(&test_vec), expected, "length {length}");
+            let (sorted, inversions) = merge_sort_with_inversions(&test_vec);
+            assert_eq!(inversions, expected, "length {length}");
+            assert_eq!(sorted, merge_sort(&test_vec));
+        }
+        let mut values: Vec<u32> = (0..1000).rev().collect();
+        assert_eq!(
+            merge_sort_in_place_with_inversions(&mut values),
+            1000 * 999 / 2
+        );
+        assert_eq!(merge_sort_in_place_with_inversions(&mut values), 0);
+    }
+
+    #[test]
+    fn sort_with_buffer_of_any_length() {
+        let test_vec: Vec<(u32, u32)> = scrambled(300, 23).zip(0..).collect();
+        let mut expected = test_vec.clone();
+        expected.sort_by_key(|v| v.0);
+        for buffer_length in [0, 1, 17, 299, 300, 400] {
+            let mut buffer = Box::new_uninit_slice(buffer_length);
+            let mut values = test_vec.clone();
+            merge_sort_with_buffer_by(&mut values, &mut buffer, |a, b| a.0.cmp(&b.0));
+            assert_eq!(values, expected, "buffer of {buffer_length}");
+            let mut values: Vec<u32> = test_vec.iter().map(|v| v.0).rev().collect();
+            let mut buffer = Box::new_uninit_slice(buffer_length);
+            merge_sort_with_buffer(&mut values[..299], &mut buffer);
+            assert!(values[..299].is_sorted(), "buffer of {buffer_length}");
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_with_config() {
+        let test_vec: Vec<u32> = scrambled(500, 101).collect();
+        let mut expected = test_vec.clone();
+        expected.sort();
+        for small_sort in [SmallSort::Insertion, SmallSort::Network] {
+            for small_sort_threshold in [0, 1, 3, 4, 8, 13, 16, 600] {
+                for scratch in [Scratch::Full, Scratch::Sqrt, Scratch::Constant] {
+                    let config = SortConfig {
+                        small_sort,
+                        small_sort_threshold,
+                        scratch,
+                    };
+                    assert_eq!(merge_sort_with_config(&test_vec, config), expected);
+                }
+            }
+        }
+    }
+
+    // Neither `Clone` nor `Copy`, counts its drops
+    #[cfg(feature = "alloc")]
+    struct DropCounter<'a> {
+        key: u32,
+        drops: &'a std::cell::Cell<usize>,
+    }
+    #[cfg(feature = "alloc")]
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_with_stats() {
+        let test_vec: Vec<u32> = scrambled(1000, 1009).collect();
+        let (sorted, stats) = merge_sort_with_stats(&test_vec);
+        assert_eq!(sorted, merge_sort(&test_vec));
+        let mut comparisons = 0;
+        merge_sort_by(&test_vec, |a, b| {
+            comparisons += 1;
+            a.cmp(b)
+        });
+        assert_eq!(stats.comparisons, comparisons);
+        // The blocks of 16 values, then 6 merge passes up to 1024 values
+        assert_eq!(stats.passes, 7);
+        #[cfg(feature = "std")]
+        assert_eq!(stats.pass_times.len(), 7);
+        assert_eq!(stats.moves, 6 * 1000);
+        // The copy of the input and the buffer
+        assert_eq!(stats.allocations, 2);
+        // After an odd number of merge passes the values are moved back from the buffer
+        let (_, stats) = merge_sort_with_stats(&test_vec[..500]);
+        assert_eq!(stats.passes, 6);
+        assert_eq!(stats.moves, 6 * 500);
+        assert_eq!(merge_sort_with_stats::<u32>(&[]).1, SortStats::default());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn sort_move_only_values() {
+        let drops = std::cell::Cell::new(0);
+        let keys: Vec<u32> = scrambled(300, 101).collect();
+        let mut expected = keys.clone();
+        expected.sort();
+        let mut test_vec: Vec<DropCounter> = keys
+            .iter()
+            .map(|&key| DropCounter { key, drops: &drops })
+            .collect();
+        merge_sort_in_place_by_key(&mut test_vec, |v| v.key);
+        assert_eq!(
+            test_vec.iter().map(|v| v.key).collect::<Vec<u32>>(),
+            expected
+        );
+        timsort_in_place_by(&mut test_vec, |a, b| b.key.cmp(&a.key));
+        merge_sort_natural_in_place_by(&mut test_vec, |a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            test_vec.iter().map(|v| v.key).collect::<Vec<u32>>(),
+            expected
+        );
+        assert_eq!(drops.get(), 0);
+        drop(test_vec);
+        assert_eq!(drops.get(), 300);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn panicking_comparison_keeps_every_value() {
+        let drops = std::cell::Cell::new(0);
+        let keys: Vec<u32> = scrambled(200, 101).collect();
+        let mut test_vec: Vec<DropCounter> = keys
+            .iter()
+            .map(|&key| DropCounter { key, drops: &drops })
+            .collect();
+        let mut comparisons = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            merge_sort_in_place_by(&mut test_vec, |a, b| {
+                comparisons += 1;
+                assert!(comparisons < 1000, "comparison limit");
+                a.key.cmp(&b.key)
+            })
+        }));
+        assert!(result.is_err());
+        let mut remaining: Vec<u32> = test_vec.iter().map(|v| v.key).collect();
+        remaining.sort();
+        let mut expected = keys.clone();
+        expected.sort();
+        assert_eq!(remaining, expected);
+        assert_eq!(drops.get(), 0);
+        drop(test_vec);
+        assert_eq!(drops.get(), 200);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn panic_at_random_comparisons() {
+        let drops = std::cell::Cell::new(0);
+        let mut keys: Vec<u32> = scrambled(150, 61).collect();
+        keys.extend(0..50);
+        let mut expected = keys.clone();
+        expected.sort();
+        // Xorshift, enough to spread the panics over the passes
+        let mut seed = 0x2545_f491_u64;
+        let sqrt_scratch = SortConfig {
+            scratch: Scratch::Sqrt,
+            ..SortConfig::default()
+        };
+        let constant_scratch = SortConfig {
+            scratch: Scratch::Constant,
+            ..SortConfig::default()
+        };
+        for round in 0..80 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let panic_at = seed % 1500;
+            let mut test_vec: Vec<DropCounter> = keys
+                .iter()
+                .map(|&key| DropCounter { key, drops: &drops })
+                .collect();
+            let mut comparisons = 0;
+            let mut compare = |a: &DropCounter, b: &DropCounter| {
+                comparisons += 1;
+                assert!(comparisons < panic_at, "injected panic");
+                a.key.cmp(&b.key)
+            };
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match round % 5 {
+                0 => merge_sort_in_place_by(&mut test_vec, &mut compare),
+                1 => merge_sort_natural_in_place_by(&mut test_vec, &mut compare),
+                2 => timsort_in_place_by(&mut test_vec, &mut compare),
+                3 => merge_sort_impl(&mut test_vec, sqrt_scratch, &mut |a, b| {
+                    compare(a, b) == Ordering::Less
+                }),
+                _ => merge_sort_impl(&mut test_vec, constant_scratch, &mut |a, b| {
+                    compare(a, b) == Ordering::Less
+                }),
+            }));
+            let mut remaining: Vec<u32> = test_vec.iter().map(|v| v.key).collect();
+            remaining.sort();
+            assert_eq!(remaining, expected);
+            assert_eq!(drops.get(), 0);
+            drop(test_vec);
+            assert_eq!(drops.get(), keys.len());
+            drops.set(0);
+        }
+    }
 }
@@ -0,0 +1,113 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use super::{SortConfig, SortTraits, merge_sort_impl};
+
+/// Indices that would sort the values, equal values keep their original order:
+/// `values[indices[0]]` is the smallest value.
+pub fn merge_argsort<T: SortTraits>(values: &[T]) -> Vec<usize> {
+    argsort_impl(values, &mut |a: &T, b: &T| a < b)
+}
+
+pub fn merge_argsort_by<T, F>(values: &[T], mut compare: F) -> Vec<usize>
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    argsort_impl(values, &mut |a: &T, b: &T| compare(a, b) == Ordering::Less)
+}
+
+pub fn merge_argsort_by_key<T, K, F>(values: &[T], mut f: F) -> Vec<usize>
+where
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    argsort_impl(values, &mut |a: &T, b: &T| f(a) < f(b))
+}
+
+fn argsort_impl<T, F: FnMut(&T, &T) -> bool>(values: &[T], is_less: &mut F) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    merge_sort_impl(
+        &mut indices,
+        SortConfig::default(),
+        &mut |a: &usize, b: &usize| is_less(&values[*a], &values[*b]),
+    );
+    indices
+}
+
+/// Reorders the values in place so that `values[i]` becomes the former
+/// `values[permutation[i]]`, as given by `merge_argsort`.
+/// Panics if `permutation` is not a permutation of the indices of `values`.
+pub fn apply_permutation<T>(values: &mut [T], permutation: &[usize]) {
+    assert_eq!(
+        values.len(),
+        permutation.len(),
+        "The permutation should be as long as the values"
+    );
+    let mut seen = vec![false; permutation.len()];
+    for &index in permutation {
+        assert!(
+            index < seen.len() && !seen[index],
+            "The indices should be a permutation"
+        );
+        seen[index] = true;
+    }
+    permute_unchecked(values, &mut permutation.to_vec());
+}
+
+// Same as `apply_permutation` without checking the indices, which are overwritten.
+// Follows the permutation cycles, as done in the standard library.
+pub(super) fn permute_unchecked<T>(values: &mut [T], indices: &mut [usize]) {
+    for i in 0..values.len() {
+        let mut index = indices[i];
+        while index < i {
+            index = indices[index];
+        }
+        indices[i] = index;
+        values.swap(i, index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn argsort_test() {
+        let test_vec = [15, 53, 1, 24, 15, 3];
+        let indices = merge_argsort(&test_vec);
+        // Equal values keep their original order
+        assert_eq!(indices, vec![2, 5, 0, 4, 3, 1]);
+        assert_eq!(
+            merge_argsort_by(&test_vec, |a, b| b.cmp(a)),
+            vec![1, 3, 0, 4, 5, 2]
+        );
+        assert_eq!(
+            merge_argsort_by_key(&test_vec, |v| v % 10),
+            vec![2, 1, 5, 3, 0, 4]
+        );
+        assert!(merge_argsort::<u32>(&[]).is_empty());
+    }
+
+    #[test]
+    fn apply_permutation_test() {
+        let keys: Vec<u32> = scrambled(200, 61).collect();
+        let mut names: Vec<String> = keys.iter().map(|k| format!("{k}")).collect();
+        let mut sorted_keys = keys.clone();
+        let indices = merge_argsort(&keys);
+        apply_permutation(&mut sorted_keys, &indices);
+        apply_permutation(&mut names, &indices);
+        let mut expected = keys.clone();
+        expected.sort();
+        assert_eq!(sorted_keys, expected);
+        let expected: Vec<String> = expected.iter().map(|k| format!("{k}")).collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "The indices should be a permutation")]
+    fn apply_permutation_rejects_duplicates() {
+        apply_permutation(&mut [1, 2, 3], &[0, 0, 2]);
+    }
+}
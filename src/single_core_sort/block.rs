@@ -0,0 +1,503 @@
+use core::cmp::Ordering;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+
+use super::rotation::{merge_in_place, merge_with_scratch, rotation_merge_sort_impl};
+use super::small_sort::insertion_sort;
+use super::{MergeHole, PassObserver, SortConfig, SortTraits, sort_blocks};
+
+/// Stable merge sort without any buffer, after GrailSort. About 3√n distinct values
+/// are taken from the input: some tag the blocks of the runs so that the blocks can
+/// be reordered, the others serve as the buffer of the merges and are only ever
+/// exchanged with the values. Makes O(n log n) comparisons and moves, and never
+/// allocates. Inputs with fewer distinct values are merged with rotations instead,
+/// like `rotation_merge_sort_in_place`.
+pub fn block_merge_sort_in_place<T: SortTraits>(values: &mut [T]) {
+    block_merge_sort_impl(
+        values,
+        &mut [],
+        SortConfig::default(),
+        &mut |a: &T, b: &T| a < b,
+        &mut (),
+    );
+}
+
+pub fn block_merge_sort_in_place_by<T, F>(values: &mut [T], mut compare: F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    block_merge_sort_impl(
+        values,
+        &mut [],
+        SortConfig::default(),
+        &mut |a: &T, b: &T| compare(a, b) == Ordering::Less,
+        &mut (),
+    );
+}
+
+// The merges use the scratch space when it holds at least √n values,
+// distinct values taken from the input otherwise
+pub(super) fn block_merge_sort_impl<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    scratch: &mut [MaybeUninit<T>],
+    config: SortConfig,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) {
+    let length = values.len();
+    let block_size = config.block_size();
+    if length <= block_size {
+        sort_blocks(values, config, is_less, observer);
+        return;
+    }
+    let buffer_length = if scratch.len() < length.isqrt() {
+        length.isqrt()
+    } else {
+        0
+    };
+    // The blocks are more than half the buffer, so the merges never have more
+    // blocks than that
+    let tags_length = 2 * length / scratch.len().max(buffer_length) + 1;
+    let keys = extract_keys(values, tags_length + buffer_length, is_less);
+    if keys < tags_length + buffer_length {
+        // Taking the keys out kept the order of the equal values, so sorting
+        // all the values is still stable
+        sort_blocks(values, config, is_less, observer);
+        rotation_merge_sort_impl(values, scratch, block_size, is_less, observer);
+        return;
+    }
+
+    let (tags, rest) = values.split_at_mut(tags_length);
+    let (buffer, data) = rest.split_at_mut(buffer_length);
+    sort_blocks(data, config, is_less, observer);
+    if buffer_length > 0 {
+        merge_passes(data, block_size, tags, buffer, is_less, observer);
+    } else {
+        merge_passes(data, block_size, tags, scratch, is_less, observer);
+    }
+    // Each key is the first of its equal values, so it goes back before them
+    insertion_sort(&mut values[..keys], is_less);
+    merge_in_place(values, keys, scratch, is_less);
+}
+
+// Gathers up to `count` distinct values at the start of the values, sorted, keeping
+// the first value of each group of equal ones. The other values keep their order.
+// Returns the number of values gathered.
+fn extract_keys<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    count: usize,
+    is_less: &mut F,
+) -> usize {
+    if values.is_empty() || count == 0 {
+        return 0;
+    }
+    // The keys are values[start..start + found], they are moved along with the scan
+    let mut start = 0;
+    let mut found = 1;
+    for id in 1..values.len() {
+        if found == count {
+            break;
+        }
+        let keys = &values[start..start + found];
+        let position = keys.partition_point(|key| is_less(key, &values[id]));
+        if position == found || is_less(&values[id], &keys[position]) {
+            // Moves the keys just before the new one, then inserts it
+            values[start..id].rotate_left(found);
+            start = id - found;
+            values[start + position..=id].rotate_right(1);
+            found += 1;
+        }
+    }
+    values[..start + found].rotate_right(found);
+    found
+}
+
+// Bottom-up merge passes on blocks of `block_size` sorted values
+fn merge_passes<T, F, B>(
+    values: &mut [T],
+    block_size: usize,
+    tags: &mut [T],
+    buffer: &mut B,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) where
+    F: FnMut(&T, &T) -> bool,
+    B: MergeBuffer<T> + ?Sized,
+{
+    let length = values.len();
+    let mut bin_size = block_size;
+    while bin_size < length {
+        observer.start_pass();
+        for bins in values.chunks_mut(2 * bin_size) {
+            if bins.len() > bin_size {
+                block_merge(bins, bin_size, tags, buffer, is_less);
+            }
+        }
+        bin_size *= 2;
+        observer.finish_pass();
+    }
+}
+
+// Stable merge of values[..mid] and values[mid..]
+fn block_merge<T, F, B>(
+    values: &mut [T],
+    mid: usize,
+    tags: &mut [T],
+    buffer: &mut B,
+    is_less: &mut F,
+) where
+    F: FnMut(&T, &T) -> bool,
+    B: MergeBuffer<T> + ?Sized,
+{
+    let length = values.len();
+    if !is_less(&values[mid], &values[mid - 1]) {
+        return;
+    }
+    if mid <= buffer.capacity() {
+        buffer.merge_low(values, mid, true, is_less);
+        return;
+    }
+    if length - mid <= buffer.capacity() {
+        buffer.merge_high(values, mid, is_less);
+        return;
+    }
+    // Largest blocks that fit in the buffer and split the left bin evenly
+    let mut block = mid;
+    while block > buffer.capacity() && block.is_multiple_of(2) {
+        block /= 2;
+    }
+    if block > buffer.capacity() || length / block > tags.len() {
+        merge_in_place(values, mid, buffer.scratch(), is_less);
+        return;
+    }
+    merge_blocks(values, mid, block, tags, buffer, is_less);
+}
+
+// Sorts the blocks of both bins by their first value, the tags keeping the equal
+// first values in the order of the bins, then merges each block with what is left
+// of the blocks of the other bin before it. The values of the right bin that do
+// not fill a block are merged last.
+fn merge_blocks<T, F, B>(
+    values: &mut [T],
+    mid: usize,
+    block: usize,
+    tags: &mut [T],
+    buffer: &mut B,
+    is_less: &mut F,
+) where
+    F: FnMut(&T, &T) -> bool,
+    B: MergeBuffer<T> + ?Sized,
+{
+    let length = values.len();
+    let blocks = length / block;
+    // The tags are sorted, the ones of the left blocks are smaller than the tag of
+    // the first right block, whose position is followed through the selection sort
+    let tags = &mut tags[..blocks];
+    let mut first_right = mid / block;
+    for id in 0..blocks {
+        let mut min = id;
+        for other in id + 1..blocks {
+            let (head, min_head) = (&values[other * block], &values[min * block]);
+            if is_less(head, min_head)
+                || (!is_less(min_head, head) && is_less(&tags[other], &tags[min]))
+            {
+                min = other;
+            }
+        }
+        if min != id {
+            let (before, after) = values.split_at_mut(min * block);
+            before[id * block..(id + 1) * block].swap_with_slice(&mut after[..block]);
+            tags.swap(id, min);
+            if first_right == id {
+                first_right = min;
+            } else if first_right == min {
+                first_right = id;
+            }
+        }
+    }
+
+    // values[pending..] is merged but not in its final place yet, it comes from the
+    // left bin if `pending_left`
+    let mut pending = 0;
+    let mut pending_left = is_less(&tags[0], &tags[first_right]);
+    for id in 1..blocks {
+        let next_left = is_less(&tags[id], &tags[first_right]);
+        let start = id * block;
+        if next_left == pending_left {
+            pending = start;
+            continue;
+        }
+        // Equal values of the left bin come first
+        let bins = &mut values[pending..start + block];
+        let (rest, rest_left) = buffer.merge_low(bins, start - pending, pending_left, is_less);
+        pending += rest;
+        if !rest_left {
+            pending_left = next_left;
+        }
+    }
+    if length > blocks * block {
+        buffer.merge_high(values, blocks * block, is_less);
+    }
+    insertion_sort(tags, is_less);
+}
+
+// Space the merges move one of the sides to: scratch space, or distinct values of
+// the input whose order does not matter and which are exchanged with the values
+trait MergeBuffer<T> {
+    fn capacity(&self) -> usize;
+
+    // Scratch space for the merges with rotations
+    fn scratch(&mut self) -> &mut [MaybeUninit<T>];
+
+    // Merge of values[..mid], which fits in the buffer, with values[mid..] until one
+    // side is used up. Returns where the values left of the other side start, and
+    // whether they come from the left side.
+    fn merge_low<F: FnMut(&T, &T) -> bool>(
+        &mut self,
+        values: &mut [T],
+        mid: usize,
+        left_wins_ties: bool,
+        is_less: &mut F,
+    ) -> (usize, bool);
+
+    // Stable merge of values[..mid] with values[mid..], which fits in the buffer
+    fn merge_high<F: FnMut(&T, &T) -> bool>(
+        &mut self,
+        values: &mut [T],
+        mid: usize,
+        is_less: &mut F,
+    );
+}
+
+impl<T> MergeBuffer<T> for [MaybeUninit<T>] {
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    fn scratch(&mut self) -> &mut [MaybeUninit<T>] {
+        self
+    }
+
+    fn merge_low<F: FnMut(&T, &T) -> bool>(
+        &mut self,
+        values: &mut [T],
+        mid: usize,
+        left_wins_ties: bool,
+        is_less: &mut F,
+    ) -> (usize, bool) {
+        // SAFETY: the left side fits in the scratch space
+        unsafe { merge_with_scratch(values, mid, self, left_wins_ties, is_less) }
+    }
+
+    fn merge_high<F: FnMut(&T, &T) -> bool>(
+        &mut self,
+        values: &mut [T],
+        mid: usize,
+        is_less: &mut F,
+    ) {
+        let length = values.len();
+        let values = values.as_mut_ptr();
+        // SAFETY: the right side fits in the scratch space. The hole is between the
+        // left values not merged yet and the merged ones, dropping it moves the right
+        // values left in the scratch space into it, including when a comparison panics.
+        unsafe {
+            let buffer = self.as_mut_ptr() as *mut T;
+            ptr::copy_nonoverlapping(values.add(mid), buffer, length - mid);
+            let mut hole = MergeHole {
+                buffer,
+                start: 0,
+                end: length - mid,
+                dest: values.add(mid),
+            };
+            let mut left = mid;
+            while left > 0 && hole.end > 0 {
+                let dest = values.add(left + hole.end - 1);
+                if is_less(&*buffer.add(hole.end - 1), &*values.add(left - 1)) {
+                    left -= 1;
+                    ptr::copy_nonoverlapping(values.add(left), dest, 1);
+                    hole.dest = values.add(left);
+                } else {
+                    hole.end -= 1;
+                    ptr::copy_nonoverlapping(buffer.add(hole.end), dest, 1);
+                }
+            }
+        }
+    }
+}
+
+impl<T> MergeBuffer<T> for [T] {
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    fn scratch(&mut self) -> &mut [MaybeUninit<T>] {
+        &mut []
+    }
+
+    fn merge_low<F: FnMut(&T, &T) -> bool>(
+        &mut self,
+        values: &mut [T],
+        mid: usize,
+        left_wins_ties: bool,
+        is_less: &mut F,
+    ) -> (usize, bool) {
+        let length = values.len();
+        self[..mid].swap_with_slice(&mut values[..mid]);
+        // values[dest..right] holds the buffer values taken out by the left side
+        let (mut left, mut right, mut dest) = (0, mid, 0);
+        while left < mid && right < length {
+            let take_right = if left_wins_ties {
+                is_less(&values[right], &self[left])
+            } else {
+                !is_less(&self[left], &values[right])
+            };
+            if take_right {
+                values.swap(dest, right);
+                right += 1;
+            } else {
+                mem::swap(&mut values[dest], &mut self[left]);
+                left += 1;
+            }
+            dest += 1;
+        }
+        if left < mid {
+            values[dest..].swap_with_slice(&mut self[left..mid]);
+            (dest, true)
+        } else {
+            (right, false)
+        }
+    }
+
+    fn merge_high<F: FnMut(&T, &T) -> bool>(
+        &mut self,
+        values: &mut [T],
+        mid: usize,
+        is_less: &mut F,
+    ) {
+        let length = values.len();
+        self[..length - mid].swap_with_slice(&mut values[mid..]);
+        // values[left..left + right] holds the buffer values taken out by the right side
+        let (mut left, mut right) = (mid, length - mid);
+        while left > 0 && right > 0 {
+            let dest = left + right - 1;
+            if is_less(&self[right - 1], &values[left - 1]) {
+                values.swap(dest, left - 1);
+                left -= 1;
+            } else {
+                mem::swap(&mut values[dest], &mut self[right - 1]);
+                right -= 1;
+            }
+        }
+        values[left..left + right].swap_with_slice(&mut self[..right]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn extract_keys_test() {
+        let mut values = [
+            (3, 'a'),
+            (1, 'b'),
+            (3, 'c'),
+            (2, 'd'),
+            (1, 'e'),
+            (0, 'f'),
+            (2, 'g'),
+        ];
+        let found = extract_keys(&mut values, 3, &mut |a, b| a.0 < b.0);
+        assert_eq!(found, 3);
+        assert_eq!(
+            values,
+            [
+                (1, 'b'),
+                (2, 'd'),
+                (3, 'a'),
+                (3, 'c'),
+                (1, 'e'),
+                (0, 'f'),
+                (2, 'g')
+            ]
+        );
+        let found = extract_keys(&mut values, 10, &mut |a, b| a.0 < b.0);
+        assert_eq!(found, 4);
+        assert_eq!(values[..4], [(0, 'f'), (1, 'b'), (2, 'd'), (3, 'a')]);
+        assert_eq!(values[4..], [(3, 'c'), (1, 'e'), (2, 'g')]);
+    }
+
+    #[test]
+    fn block_merge_with_both_buffers() {
+        // Runs of equal values cross the blocks, the right bin does not fill its last block
+        let test_vec: Vec<(u32, u32)> = scrambled(300, 37).zip(0..).collect();
+        let mut is_less = |a: &(u32, u32), b: &(u32, u32)| a.0 < b.0;
+        let mut tags: Vec<(u32, u32)> = (0..80).map(|tag| (tag, 0)).collect();
+        for (mid, buffer_length) in [
+            (128, 16),
+            (128, 20),
+            (160, 9),
+            (200, 7),
+            (64, 64),
+            (290, 16),
+        ] {
+            let mut input = test_vec.clone();
+            input[..mid].sort_by_key(|v| v.0);
+            input[mid..].sort_by_key(|v| v.0);
+            let mut expected = input.clone();
+            expected.sort_by_key(|v| v.0);
+
+            let mut values = input.clone();
+            let mut scratch = Box::new_uninit_slice(buffer_length);
+            block_merge(&mut values, mid, &mut tags, &mut scratch[..], &mut is_less);
+            assert_eq!(values, expected, "mid {mid}, scratch of {buffer_length}");
+            assert!(tags.is_sorted());
+
+            let mut values = input;
+            let mut buffer: Vec<(u32, u32)> =
+                (1000..).take(buffer_length).map(|v| (v, 0)).collect();
+            block_merge(&mut values, mid, &mut tags, &mut buffer[..], &mut is_less);
+            assert_eq!(values, expected, "mid {mid}, buffer of {buffer_length}");
+            assert!(tags.is_sorted());
+            buffer.sort();
+            assert!(
+                buffer
+                    .iter()
+                    .map(|v| v.0)
+                    .eq(1000..1000 + buffer_length as u32)
+            );
+        }
+    }
+
+    #[test]
+    fn block_merge_sort_is_stable() {
+        for length in [0, 1, 2, 17, 100, 257, 1000, 4099, 10000] {
+            for distinct in [3, 61, 1009, 30011] {
+                let test_vec: Vec<(u32, u32)> = scrambled(length, distinct).zip(0..).collect();
+                let mut expected = test_vec.clone();
+                expected.sort_by_key(|v| v.0);
+                let mut values = test_vec.clone();
+                block_merge_sort_in_place_by(&mut values, |a, b| a.0.cmp(&b.0));
+                assert_eq!(values, expected, "length {length}, {distinct} distinct");
+
+                let mut values = test_vec.clone();
+                let mut scratch = Box::new_uninit_slice(length.isqrt());
+                block_merge_sort_impl(
+                    &mut values,
+                    &mut scratch,
+                    SortConfig::default(),
+                    &mut |a, b| a.0 < b.0,
+                    &mut (),
+                );
+                assert_eq!(
+                    values, expected,
+                    "length {length}, {distinct} distinct, scratch"
+                );
+            }
+            let mut values: Vec<u32> = (0..length as u32).rev().collect();
+            block_merge_sort_in_place(&mut values);
+            assert!(values.is_sorted());
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use super::{SortConfig, apply_permutation, merge_sort_impl};
+
+/// Column of a table, reordered along with the key columns by `sort_columns`.
+pub trait Column {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Same as `apply_permutation`.
+    fn permute(&mut self, permutation: &[usize]);
+}
+
+/// Column the rows of a table are sorted by.
+pub trait KeyColumn: Column {
+    fn compare_rows(&self, row1: usize, row2: usize) -> Ordering;
+}
+
+impl<T> Column for Vec<T> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn permute(&mut self, permutation: &[usize]) {
+        apply_permutation(self, permutation);
+    }
+}
+
+impl<T: Ord> KeyColumn for Vec<T> {
+    fn compare_rows(&self, row1: usize, row2: usize) -> Ordering {
+        self[row1].cmp(&self[row2])
+    }
+}
+
+/// Sorts the rows of a table stored as columns, by the first key column, then the
+/// second one for equal keys and so on. The rows are never built: the row indices
+/// are sorted, then every column is permuted in place. Equal rows keep their order.
+/// Panics if the columns do not all have the same length.
+pub fn sort_columns(keys: &mut [&mut dyn KeyColumn], columns: &mut [&mut dyn Column]) {
+    let permutation = argsort_rows(keys, columns);
+    for key in keys.iter_mut() {
+        key.permute(&permutation);
+    }
+    for column in columns.iter_mut() {
+        column.permute(&permutation);
+    }
+}
+
+fn argsort_rows(keys: &[&mut dyn KeyColumn], columns: &[&mut dyn Column]) -> Vec<usize> {
+    let length = columns_length(keys, columns);
+    let mut indices: Vec<usize> = (0..length).collect();
+    merge_sort_impl(
+        &mut indices,
+        SortConfig::default(),
+        &mut |a: &usize, b: &usize| compare_rows(keys, *a, *b) == Ordering::Less,
+    );
+    indices
+}
+
+// Lexicographic order of the rows on the key columns
+pub(crate) fn compare_rows<K: KeyColumn + ?Sized>(
+    keys: &[&mut K],
+    row1: usize,
+    row2: usize,
+) -> Ordering {
+    keys.iter()
+        .map(|key| key.compare_rows(row1, row2))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+pub(crate) fn columns_length<K, C>(keys: &[&mut K], columns: &[&mut C]) -> usize
+where
+    K: KeyColumn + ?Sized,
+    C: Column + ?Sized,
+{
+    let length = keys.first().map_or(0, |key| key.len());
+    let lengths = keys.iter().map(|key| key.len());
+    assert!(
+        lengths
+            .chain(columns.iter().map(|column| column.len()))
+            .all(|column_length| column_length == length),
+        "The columns should all have the same length"
+    );
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_columns_test() {
+        let mut ids: Vec<u64> = vec![3, 1, 2, 1, 3, 2];
+        let mut versions: Vec<u32> = vec![0, 5, 1, 2, 0, 1];
+        let mut names = vec!["a", "b", "c", "d", "e", "f"];
+        let mut weights = vec![0.5, 1.5, 2.5, 3.5, 4.5, 5.5];
+        sort_columns(
+            &mut [&mut ids, &mut versions],
+            &mut [&mut names, &mut weights],
+        );
+        assert_eq!(ids, vec![1, 1, 2, 2, 3, 3]);
+        assert_eq!(versions, vec![2, 5, 1, 1, 0, 0]);
+        // Equal rows keep their order
+        assert_eq!(names, vec!["d", "b", "c", "f", "a", "e"]);
+        assert_eq!(weights, vec![3.5, 1.5, 2.5, 5.5, 0.5, 4.5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The columns should all have the same length")]
+    fn sort_columns_of_different_lengths() {
+        let mut ids: Vec<u64> = vec![3, 1, 2];
+        let mut names = vec!["a", "b"];
+        sort_columns(&mut [&mut ids], &mut [&mut names]);
+    }
+}
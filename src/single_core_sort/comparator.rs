@@ -0,0 +1,105 @@
+use core::cmp::Ordering;
+
+/// Compares by the key, ascending. Chain more keys with the methods of `ThenBy`:
+/// `by(|e: &Employee| e.department).then_desc(|e| e.salary)` can be given
+/// to any of the `_by` sorts.
+pub fn by<T, K, F>(key: F) -> impl Fn(&T, &T) -> Ordering + Clone
+where
+    K: Ord,
+    F: Fn(&T) -> K + Clone,
+{
+    move |a: &T, b: &T| key(a).cmp(&key(b))
+}
+
+/// Compares by the key, descending.
+pub fn by_desc<T, K, F>(key: F) -> impl Fn(&T, &T) -> Ordering + Clone
+where
+    K: Ord,
+    F: Fn(&T) -> K + Clone,
+{
+    move |a: &T, b: &T| key(b).cmp(&key(a))
+}
+
+/// Composition of comparators, the next comparison is only made for equal elements.
+pub trait ThenBy<T>: Fn(&T, &T) -> Ordering + Clone {
+    fn then<K, F>(self, key: F) -> impl Fn(&T, &T) -> Ordering + Clone
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Clone,
+    {
+        self.then_by(by(key))
+    }
+
+    fn then_desc<K, F>(self, key: F) -> impl Fn(&T, &T) -> Ordering + Clone
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Clone,
+    {
+        self.then_by(by_desc(key))
+    }
+
+    fn then_by<G>(self, compare: G) -> impl Fn(&T, &T) -> Ordering + Clone
+    where
+        G: Fn(&T, &T) -> Ordering + Clone,
+    {
+        move |a: &T, b: &T| self(a, b).then_with(|| compare(a, b))
+    }
+
+    fn reversed(self) -> impl Fn(&T, &T) -> Ordering + Clone {
+        move |a: &T, b: &T| self(b, a)
+    }
+}
+
+impl<T, C: Fn(&T, &T) -> Ordering + Clone> ThenBy<T> for C {}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::single_core_sort::merge_sort_by;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Employee {
+        department: u32,
+        salary: u32,
+        name: &'static str,
+    }
+
+    #[test]
+    fn sort_by_several_keys() {
+        let employee = |department, salary, name| Employee {
+            department,
+            salary,
+            name,
+        };
+        let employees = [
+            employee(2, 100, "d"),
+            employee(1, 200, "c"),
+            employee(2, 300, "b"),
+            employee(1, 200, "a"),
+            employee(2, 100, "a"),
+        ];
+        let compare = by(|e: &Employee| e.department)
+            .then_desc(|e| e.salary)
+            .then(|e| e.name);
+        let names: Vec<&str> = merge_sort_by(&employees, compare.clone())
+            .iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["a", "c", "b", "a", "d"]);
+
+        let names: Vec<&str> = merge_sort_by(&employees, compare.reversed())
+            .iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["d", "a", "b", "c", "a"]);
+
+        // Equal elements keep their order
+        let compare =
+            by_desc(|e: &Employee| e.salary).then_by(|a, b| a.department.cmp(&b.department));
+        let names: Vec<&str> = merge_sort_by(&employees, compare)
+            .iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["b", "c", "a", "d", "a"]);
+    }
+}
@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
+use core::ptr;
+
+use super::{SortConfig, SortTraits, merge_sort_impl};
+
+/// Which of the equal elements `merge_sort_dedup` keeps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keep {
+    /// The first one in the input.
+    First,
+    /// The last one in the input.
+    Last,
+}
+
+/// Sorted copy of the input with a single element of each group of equal ones.
+/// The duplicates are dropped during the last merge pass rather than after it.
+pub fn merge_sort_dedup<T: SortTraits + Clone>(input: &[T], keep: Keep) -> Vec<T> {
+    dedup_impl(input.to_vec(), keep, &mut |a: &T, b: &T| a < b)
+}
+
+pub fn merge_sort_dedup_by<T, F>(input: &[T], keep: Keep, mut compare: F) -> Vec<T>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    dedup_impl(input.to_vec(), keep, &mut |a: &T, b: &T| {
+        compare(a, b) == Ordering::Less
+    })
+}
+
+pub fn merge_sort_dedup_by_key<T, K, F>(input: &[T], keep: Keep, mut f: F) -> Vec<T>
+where
+    T: Clone,
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    dedup_impl(input.to_vec(), keep, &mut |a: &T, b: &T| f(a) < f(b))
+}
+
+/// Sorts the values by key and folds each run of equal keys into an aggregate,
+/// starting from `A::default()`: `|count: &mut usize, _| *count += 1` counts the
+/// values of each key. The key of each value is computed once, the runs are
+/// folded during the last merge pass and their values come in their original order.
+pub fn group_by_sorted<T, K, A, F, G>(values: Vec<T>, mut key: F, mut aggregate: G) -> Vec<(K, A)>
+where
+    K: Ord,
+    A: Default,
+    F: FnMut(&T) -> K,
+    G: FnMut(&mut A, T),
+{
+    let keyed: Vec<(K, T)> = values.into_iter().map(|v| (key(&v), v)).collect();
+    let mut groups: Vec<(K, A)> = Vec::new();
+    sort_and_emit(
+        keyed,
+        &mut |a: &(K, T), b: &(K, T)| a.0 < b.0,
+        |_, (value_key, value)| match groups.last_mut() {
+            Some((last_key, group)) if *last_key == value_key => aggregate(group, value),
+            _ => {
+                let mut group = A::default();
+                aggregate(&mut group, value);
+                groups.push((value_key, group));
+            }
+        },
+    );
+    groups
+}
+
+fn dedup_impl<T, F: FnMut(&T, &T) -> bool>(values: Vec<T>, keep: Keep, is_less: &mut F) -> Vec<T> {
+    let mut out: Vec<T> = Vec::with_capacity(values.len());
+    sort_and_emit(values, is_less, |is_less, value| match out.last_mut() {
+        // The values come sorted, so the last one is equal unless it is smaller
+        Some(last) if !is_less(last, &value) => {
+            if keep == Keep::Last {
+                *last = value;
+            }
+        }
+        _ => out.push(value),
+    });
+    out
+}
+
+// Sorts both halves of the values in place, then moves the values out to `emit` in
+// order while merging the halves, which makes the last merge pass also collapse
+// equal values
+fn sort_and_emit<T, F: FnMut(&T, &T) -> bool>(
+    mut values: Vec<T>,
+    is_less: &mut F,
+    mut emit: impl FnMut(&mut F, T),
+) {
+    let length = values.len();
+    let (left, right) = values.split_at_mut(length / 2);
+    merge_sort_impl(left, SortConfig::default(), is_less);
+    merge_sort_impl(right, SortConfig::default(), is_less);
+    // SAFETY: from here on the halves own the values, the vector only frees the memory
+    unsafe { values.set_len(0) };
+    let mut halves = SortedHalves {
+        values: values.as_mut_ptr(),
+        left: 0..length / 2,
+        right: length / 2..length,
+    };
+    loop {
+        // Same rule as `merge_bins`: take from the right only when strictly smaller
+        let take_right = match (halves.left.is_empty(), halves.right.is_empty()) {
+            // SAFETY: both heads are values that have not been moved out yet
+            (false, false) => unsafe {
+                is_less(
+                    &*halves.values.add(halves.right.start),
+                    &*halves.values.add(halves.left.start),
+                )
+            },
+            (true, false) => true,
+            (_, true) => false,
+        };
+        let id = if take_right {
+            halves.right.next()
+        } else {
+            halves.left.next()
+        };
+        match id {
+            // SAFETY: the range no longer holds the value, so it is moved out once
+            Some(id) => emit(is_less, unsafe { ptr::read(halves.values.add(id)) }),
+            None => return,
+        }
+    }
+}
+
+// Values of the two sorted halves that have not been handed to `emit` yet.
+// Dropping the halves drops them, if a comparison or `emit` panics.
+struct SortedHalves<T> {
+    values: *mut T,
+    left: Range<usize>,
+    right: Range<usize>,
+}
+
+impl<T> Drop for SortedHalves<T> {
+    fn drop(&mut self) {
+        for range in [&self.left, &self.right] {
+            // SAFETY: the values of the ranges are initialized and owned by the halves
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                    self.values.add(range.start),
+                    range.len(),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn dedup_keeps_first_or_last() {
+        let test_vec: Vec<(u32, u32)> = scrambled(200, 37).zip(0..).collect();
+        let compare = |a: &(u32, u32), b: &(u32, u32)| a.0.cmp(&b.0);
+        let first = merge_sort_dedup_by(&test_vec, Keep::First, compare);
+        let last = merge_sort_dedup_by(&test_vec, Keep::Last, compare);
+        assert_eq!(first.len(), 37);
+        assert_eq!(last.len(), 37);
+        for (key, (first, last)) in std::iter::zip(&first, &last).enumerate() {
+            let positions: Vec<u32> = test_vec
+                .iter()
+                .filter(|v| v.0 == key as u32)
+                .map(|v| v.1)
+                .collect();
+            assert_eq!(*first, (key as u32, positions[0]));
+            assert_eq!(*last, (key as u32, *positions.last().unwrap()));
+        }
+        assert_eq!(
+            merge_sort_dedup(&[3, 1, 3, 2, 1], Keep::First),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            merge_sort_dedup_by_key(&test_vec, Keep::First, |v| v.0),
+            first
+        );
+        assert!(merge_sort_dedup::<u32>(&[], Keep::Last).is_empty());
+    }
+
+    #[test]
+    fn panic_drops_each_value_once() {
+        use std::rc::Rc;
+        let values: Vec<Rc<u32>> = scrambled(100, 37).map(Rc::new).collect();
+        let copies = values.clone();
+        let mut calls = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            group_by_sorted(
+                values,
+                |v| **v,
+                |_: &mut (), _| {
+                    calls += 1;
+                    assert!(calls < 50, "injected panic");
+                },
+            )
+        }));
+        assert!(result.is_err());
+        assert!(copies.iter().all(|v| Rc::strong_count(v) == 1));
+    }
+
+    #[test]
+    fn group_by_sorted_test() {
+        let orders = vec![
+            ("bob", 3),
+            ("alice", 5),
+            ("bob", 1),
+            ("carol", 2),
+            ("alice", 4),
+        ];
+        let totals = group_by_sorted(orders.clone(), |o| o.0, |total: &mut u32, o| *total += o.1);
+        assert_eq!(totals, vec![("alice", 9), ("bob", 4), ("carol", 2)]);
+        // The values of a group keep their order
+        let groups = group_by_sorted(orders, |o| o.0, |group: &mut Vec<u32>, o| group.push(o.1));
+        assert_eq!(
+            groups,
+            vec![
+                ("alice", vec![5, 4]),
+                ("bob", vec![3, 1]),
+                ("carol", vec![2])
+            ]
+        );
+    }
+}
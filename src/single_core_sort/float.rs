@@ -0,0 +1,173 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use super::{SortConfig, merge_sort_impl};
+
+/// Where NaN values go in the sorted output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NanPolicy {
+    First,
+    Last,
+    /// Refuse to sort, the input is left untouched.
+    Error,
+}
+
+/// How negative and positive zeros compare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignedZero {
+    /// Zeros are equal and keep their original order.
+    Equal,
+    /// -0.0 comes before +0.0, like `f64::total_cmp`.
+    NegativeFirst,
+}
+
+/// Total order used to sort floats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloatOrder {
+    pub nan: NanPolicy,
+    pub signed_zero: SignedZero,
+}
+
+impl Default for FloatOrder {
+    fn default() -> Self {
+        FloatOrder {
+            nan: NanPolicy::Last,
+            signed_zero: SignedZero::NegativeFirst,
+        }
+    }
+}
+
+/// Returned when sorting NaN values with `NanPolicy::Error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NanError;
+
+impl fmt::Display for NanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot sort NaN values with the NaN policy set to error")
+    }
+}
+
+impl Error for NanError {}
+
+pub trait SortFloat: Copy + PartialOrd {
+    fn is_nan(self) -> bool;
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+impl SortFloat for f32 {
+    fn is_nan(self) -> bool {
+        f32::is_nan(self)
+    }
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+}
+
+impl SortFloat for f64 {
+    fn is_nan(self) -> bool {
+        f64::is_nan(self)
+    }
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f64::total_cmp(self, other)
+    }
+}
+
+impl FloatOrder {
+    /// Compares two floats, NaN values are all equal to each other.
+    pub fn compare<F: SortFloat>(&self, a: &F, b: &F) -> Ordering {
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) if self.nan == NanPolicy::First => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, true) if self.nan == NanPolicy::First => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => match self.signed_zero {
+                // Without NaN the partial order is total, only the zeros are equal
+                SignedZero::Equal => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                SignedZero::NegativeFirst => a.total_cmp(b),
+            },
+        }
+    }
+
+    /// Fails if the values contain NaN and the policy is `NanPolicy::Error`.
+    pub fn check<F: SortFloat>(&self, values: &[F]) -> Result<(), NanError> {
+        if self.nan == NanPolicy::Error && values.iter().any(|v| v.is_nan()) {
+            Err(NanError)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_floats<F: SortFloat>(input: &[F], order: FloatOrder) -> Result<Vec<F>, NanError> {
+    let mut values = input.to_vec();
+    merge_sort_floats_in_place(&mut values, order)?;
+    Ok(values)
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_sort_floats_in_place<F: SortFloat>(
+    values: &mut [F],
+    order: FloatOrder,
+) -> Result<(), NanError> {
+    order.check(values)?;
+    merge_sort_impl(values, SortConfig::default(), &mut |a: &F, b: &F| {
+        order.compare(a, b) == Ordering::Less
+    });
+    Ok(())
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_with_nan_policies() {
+        let test_vec = [
+            3.0,
+            f64::NAN,
+            -1.0,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            -f64::NAN,
+        ];
+        let mut order = FloatOrder::default();
+        let sorted = merge_sort_floats(&test_vec, order).unwrap();
+        assert_eq!(sorted[..4], [f64::NEG_INFINITY, -1.0, 3.0, f64::INFINITY]);
+        assert!(sorted[4..].iter().all(|v| v.is_nan()));
+
+        order.nan = NanPolicy::First;
+        let sorted = merge_sort_floats(&test_vec, order).unwrap();
+        assert!(sorted[..2].iter().all(|v| v.is_nan()));
+        assert_eq!(sorted[2..], [f64::NEG_INFINITY, -1.0, 3.0, f64::INFINITY]);
+
+        order.nan = NanPolicy::Error;
+        let mut values = test_vec;
+        assert_eq!(
+            merge_sort_floats_in_place(&mut values, order),
+            Err(NanError)
+        );
+        assert_eq!(values.map(f64::to_bits), test_vec.map(f64::to_bits));
+        assert_eq!(merge_sort_floats(&[2.0f32, 1.0], order), Ok(vec![1.0, 2.0]));
+    }
+
+    #[test]
+    fn sort_signed_zeros() {
+        let test_vec = [0.0f32, 1.0, -0.0, -1.0, 0.0, -0.0];
+        let signs = |sorted: Vec<f32>| -> Vec<bool> {
+            sorted.iter().map(|v| v.is_sign_negative()).collect()
+        };
+        let mut order = FloatOrder::default();
+        let sorted = merge_sort_floats(&test_vec, order).unwrap();
+        assert_eq!(signs(sorted), [true, true, true, false, false, false]);
+        // Equal zeros keep their original order
+        order.signed_zero = SignedZero::Equal;
+        let sorted = merge_sort_floats(&test_vec, order).unwrap();
+        assert_eq!(signs(sorted), [true, false, true, false, true, false]);
+    }
+}
@@ -0,0 +1,281 @@
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+use core::cmp::Ordering;
+
+use super::SortTraits;
+
+/// Merges two sorted slices, equal elements of `a` come first.
+#[cfg(feature = "alloc")]
+pub fn merge<T: SortTraits + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    merge_impl(a, b, &mut |x: &T, y: &T| x < y)
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_by<T, F>(a: &[T], b: &[T], mut compare: F) -> Vec<T>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    merge_impl(a, b, &mut |x: &T, y: &T| compare(x, y) == Ordering::Less)
+}
+
+/// Merges two sorted slices into `out`, which must be exactly as long as both.
+pub fn merge_into<T: SortTraits + Clone>(a: &[T], b: &[T], out: &mut [T]) {
+    merge_into_impl(a, b, out, &mut |x: &T, y: &T| x < y);
+}
+
+pub fn merge_into_by<T, F>(a: &[T], b: &[T], out: &mut [T], mut compare: F)
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    merge_into_impl(a, b, out, &mut |x: &T, y: &T| {
+        compare(x, y) == Ordering::Less
+    });
+}
+
+/// Merges any number of sorted slices with a loser tree, equal elements come
+/// in the order of their slices.
+#[cfg(feature = "alloc")]
+pub fn merge_many<T: SortTraits + Clone>(slices: &[&[T]]) -> Vec<T> {
+    merge_many_impl(slices, &mut |x: &T, y: &T| x < y)
+}
+
+#[cfg(feature = "alloc")]
+pub fn merge_many_by<T, F>(slices: &[&[T]], mut compare: F) -> Vec<T>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    merge_many_impl(slices, &mut |x: &T, y: &T| compare(x, y) == Ordering::Less)
+}
+
+#[cfg(feature = "alloc")]
+fn merge_impl<T: Clone, F: FnMut(&T, &T) -> bool>(a: &[T], b: &[T], is_less: &mut F) -> Vec<T> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    merge_with(a, b, is_less, |v| out.push(v.clone()));
+    out
+}
+
+fn merge_into_impl<T: Clone, F: FnMut(&T, &T) -> bool>(
+    a: &[T],
+    b: &[T],
+    out: &mut [T],
+    is_less: &mut F,
+) {
+    assert_eq!(
+        a.len() + b.len(),
+        out.len(),
+        "The output length should be the sum of the input lengths"
+    );
+    let mut slots = out.iter_mut();
+    merge_with(a, b, is_less, |v| *slots.next().unwrap() = v.clone());
+}
+
+#[cfg(feature = "alloc")]
+fn merge_many_impl<T: Clone, F: FnMut(&T, &T) -> bool>(slices: &[&[T]], is_less: &mut F) -> Vec<T> {
+    let length = slices.iter().map(|slice| slice.len()).sum();
+    let mut out = Vec::with_capacity(length);
+    if slices.is_empty() {
+        return out;
+    }
+    let mut positions = vec![0; slices.len()];
+    let head = |positions: &[usize], id: usize| slices[id].get(positions[id]);
+    let mut tree = LoserTree::new(slices.len(), |id1, id2| {
+        beats(
+            head(&positions, id1),
+            id1,
+            head(&positions, id2),
+            id2,
+            is_less,
+        )
+    });
+    while let Some(value) = head(&positions, tree.winner()) {
+        out.push(value.clone());
+        positions[tree.winner()] += 1;
+        tree.replay(|id1, id2| {
+            beats(
+                head(&positions, id1),
+                id1,
+                head(&positions, id2),
+                id2,
+                is_less,
+            )
+        });
+    }
+    out
+}
+
+// Same rule as `merge_bins`: take from `b` only when strictly smaller, which keeps it stable
+fn merge_with<'a, T, F: FnMut(&T, &T) -> bool>(
+    a: &'a [T],
+    b: &'a [T],
+    is_less: &mut F,
+    mut emit: impl FnMut(&'a T),
+) {
+    let (mut id1, mut id2) = (0, 0);
+    while id1 < a.len() && id2 < b.len() {
+        if is_less(&b[id2], &a[id1]) {
+            emit(&b[id2]);
+            id2 += 1;
+        } else {
+            emit(&a[id1]);
+            id1 += 1;
+        }
+    }
+    a[id1..].iter().chain(&b[id2..]).for_each(emit);
+}
+
+// Tournament tree over the heads of k sources. The leaves are the sources,
+// each internal node keeps the loser of the match played there and the
+// overall winner is kept at the root, so that replacing the winner only
+// replays the matches on the path from its leaf to the root.
+// The matches are played by a closure telling whether a source beats another.
+#[cfg(feature = "alloc")]
+pub(super) struct LoserTree {
+    // Node 0 holds the winner, nodes 1..k the losers, leaves are the nodes k..2k
+    tree: Vec<usize>,
+}
+
+#[cfg(feature = "alloc")]
+impl LoserTree {
+    // There must be at least one source
+    pub(super) fn new(k: usize, mut beats: impl FnMut(usize, usize) -> bool) -> Self {
+        let mut tree = vec![0; k];
+        let mut winners = vec![0; 2 * k];
+        for (id, winner) in winners[k..].iter_mut().enumerate() {
+            *winner = id;
+        }
+        for node in (1..k).rev() {
+            let (left, right) = (winners[2 * node], winners[2 * node + 1]);
+            let (winner, loser) = if beats(left, right) {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            winners[node] = winner;
+            tree[node] = loser;
+        }
+        tree[0] = winners[1];
+        LoserTree { tree }
+    }
+
+    pub(super) fn winner(&self) -> usize {
+        self.tree[0]
+    }
+
+    // Finds the new winner once the head of the previous winner has changed
+    pub(super) fn replay(&mut self, mut beats: impl FnMut(usize, usize) -> bool) {
+        let mut winner = self.tree[0];
+        let mut node = (winner + self.tree.len()) / 2;
+        while node >= 1 {
+            if beats(self.tree[node], winner) {
+                core::mem::swap(&mut self.tree[node], &mut winner);
+            }
+            node /= 2;
+        }
+        self.tree[0] = winner;
+    }
+}
+
+// Whether the head of source id1 comes before the head of source id2.
+// Exhausted sources always lose, ties go to the first source to keep the merge stable.
+#[cfg(feature = "alloc")]
+pub(super) fn beats<T, F: FnMut(&T, &T) -> bool>(
+    head1: Option<&T>,
+    id1: usize,
+    head2: Option<&T>,
+    id2: usize,
+    is_less: &mut F,
+) -> bool {
+    match (head1, head2) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(head1), Some(head2)) if id1 < id2 => !is_less(head2, head1),
+        (Some(head1), Some(head2)) => is_less(head1, head2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn merge_test() {
+        let a = [1, 3, 5, 7, 9, 11];
+        let b = [2, 3, 4, 12];
+        assert_eq!(merge(&a, &b), vec![1, 2, 3, 3, 4, 5, 7, 9, 11, 12]);
+        assert_eq!(merge(&a, &[]), a.to_vec());
+        assert_eq!(
+            merge_by(&[9, 4, 4, 1], &[8, 4, 0], |x, y| y.cmp(x)),
+            vec![9, 8, 4, 4, 4, 1, 0]
+        );
+    }
+
+    #[test]
+    fn merge_into_test() {
+        let a = [1, 3, 5, 7, 9, 11];
+        let b = [2, 3, 4, 12];
+        let mut out = [0; 10];
+        merge_into(&a, &b, &mut out);
+        assert_eq!(out, [1, 2, 3, 3, 4, 5, 7, 9, 11, 12]);
+        let mut out = [0; 7];
+        merge_into_by(&[9, 4, 4, 1], &[8, 4, 0], &mut out, |x, y| y.cmp(x));
+        assert_eq!(out, [9, 8, 4, 4, 4, 1, 0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_into_wrong_length() {
+        let mut out = [0; 3];
+        merge_into(&[1, 2], &[3, 4], &mut out);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn merge_is_stable() {
+        // (key, slice id, position), sorting the concatenation stably gives the expected order
+        let make = |id: u32, length: u32| -> Vec<(u32, u32, u32)> {
+            (0..length)
+                .map(|i| ((i * 7) / 10 + id % 2, id, i))
+                .collect()
+        };
+        let (a, b) = (make(0, 40), make(1, 25));
+        let mut expected = [a.clone(), b.clone()].concat();
+        expected.sort_by_key(|v| v.0);
+        assert_eq!(merge_by(&a, &b, |x, y| x.0.cmp(&y.0)), expected);
+        let mut out = vec![(0, 0, 0); expected.len()];
+        merge_into_by(&a, &b, &mut out, |x, y| x.0.cmp(&y.0));
+        assert_eq!(out, expected);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn merge_many_test() {
+        for k in 0..12 {
+            let slices: Vec<Vec<(u32, u32)>> = (0..k)
+                .map(|id| {
+                    (0..(id * 37) % 23)
+                        .map(|i| ((i * (id + 3)) % 17, id))
+                        .collect()
+                })
+                .map(|mut slice: Vec<(u32, u32)>| {
+                    slice.sort();
+                    slice
+                })
+                .collect();
+            let slices: Vec<&[(u32, u32)]> = slices.iter().map(|slice| &slice[..]).collect();
+            let mut expected = slices.concat();
+            expected.sort_by_key(|v| v.0);
+            assert_eq!(
+                merge_many_by(&slices, |x, y| x.0.cmp(&y.0)),
+                expected,
+                "{k} slices"
+            );
+            let mut expected = slices.concat();
+            expected.sort();
+            assert_eq!(merge_many(&slices), expected, "{k} slices");
+        }
+    }
+}
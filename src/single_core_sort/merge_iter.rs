@@ -0,0 +1,306 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter::Peekable;
+
+use super::SortTraits;
+#[cfg(feature = "alloc")]
+use super::merge::{LoserTree, beats};
+
+/// Lazily merges two sorted iterators, equal elements of `a` come first.
+pub fn merge_iter<T, A, B>(
+    a: A,
+    b: B,
+) -> MergeIter<A::IntoIter, B::IntoIter, impl FnMut(&T, &T) -> bool>
+where
+    T: SortTraits,
+    A: IntoIterator<Item = T>,
+    B: IntoIterator<Item = T>,
+{
+    MergeIter::new(a.into_iter(), b.into_iter(), |x: &T, y: &T| x < y)
+}
+
+pub fn merge_iter_by<T, A, B, F>(
+    a: A,
+    b: B,
+    mut compare: F,
+) -> MergeIter<A::IntoIter, B::IntoIter, impl FnMut(&T, &T) -> bool>
+where
+    A: IntoIterator<Item = T>,
+    B: IntoIterator<Item = T>,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    MergeIter::new(a.into_iter(), b.into_iter(), move |x: &T, y: &T| {
+        compare(x, y) == Ordering::Less
+    })
+}
+
+/// Lazily merges any number of sorted iterators with a loser tree, equal elements
+/// come in the order of their iterators.
+#[cfg(feature = "alloc")]
+pub fn kmerge_iter<T, I>(
+    iters: impl IntoIterator<Item = I>,
+) -> KMergeIter<I::IntoIter, impl FnMut(&T, &T) -> bool>
+where
+    T: SortTraits,
+    I: IntoIterator<Item = T>,
+{
+    KMergeIter::new(iters, |x: &T, y: &T| x < y)
+}
+
+#[cfg(feature = "alloc")]
+pub fn kmerge_iter_by<T, I, F>(
+    iters: impl IntoIterator<Item = I>,
+    mut compare: F,
+) -> KMergeIter<I::IntoIter, impl FnMut(&T, &T) -> bool>
+where
+    I: IntoIterator<Item = T>,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    KMergeIter::new(iters, move |x: &T, y: &T| compare(x, y) == Ordering::Less)
+}
+
+/// Two-way merge of sorted iterators, see `merge_iter`.
+pub struct MergeIter<A: Iterator, B: Iterator<Item = A::Item>, F> {
+    a: Peekable<A>,
+    b: Peekable<B>,
+    is_less: F,
+    check_sorted: bool,
+    dedup: bool,
+}
+
+impl<A, B, F> MergeIter<A, B, F>
+where
+    A: Iterator,
+    B: Iterator<Item = A::Item>,
+    F: FnMut(&A::Item, &A::Item) -> bool,
+{
+    fn new(a: A, b: B, is_less: F) -> Self {
+        MergeIter {
+            a: a.peekable(),
+            b: b.peekable(),
+            is_less,
+            check_sorted: false,
+            dedup: false,
+        }
+    }
+
+    /// Panics when an input yields an element smaller than the previous one,
+    /// instead of silently producing an unsorted sequence.
+    pub fn check_sorted(mut self) -> Self {
+        self.check_sorted = true;
+        self
+    }
+
+    /// Keeps only the first of equal elements, like a set union of the inputs.
+    pub fn dedup(mut self) -> Self {
+        self.dedup = true;
+        self
+    }
+}
+
+impl<A, B, F> Iterator for MergeIter<A, B, F>
+where
+    A: Iterator,
+    B: Iterator<Item = A::Item>,
+    F: FnMut(&A::Item, &A::Item) -> bool,
+{
+    type Item = A::Item;
+
+    fn next(&mut self) -> Option<A::Item> {
+        // Same rule as `merge_bins`: take from `b` only when strictly smaller
+        let take_b = match (self.a.peek(), self.b.peek()) {
+            (Some(head_a), Some(head_b)) => (self.is_less)(head_b, head_a),
+            (None, Some(_)) => true,
+            (_, None) => false,
+        };
+        let (value, next, id) = if take_b {
+            (self.b.next()?, self.b.peek(), 1)
+        } else {
+            (self.a.next()?, self.a.peek(), 0)
+        };
+        if self.check_sorted {
+            check_order(&value, next, id, &mut self.is_less);
+        }
+        if self.dedup {
+            let is_less = &mut self.is_less;
+            let mut is_equal = |next: &A::Item| !is_less(&value, next) && !is_less(next, &value);
+            while self.a.next_if(&mut is_equal).is_some() {}
+            while self.b.next_if(&mut is_equal).is_some() {}
+        }
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = add_size_hints([self.a.size_hint(), self.b.size_hint()]);
+        if self.dedup {
+            (lower.min(1), upper)
+        } else {
+            (lower, upper)
+        }
+    }
+}
+
+/// K-way merge of sorted iterators, see `kmerge_iter`.
+#[cfg(feature = "alloc")]
+pub struct KMergeIter<I: Iterator, F> {
+    iters: Vec<I>,
+    // Next element of each iterator, filled on the first call to `next`
+    heads: Vec<Option<I::Item>>,
+    tree: Option<LoserTree>,
+    is_less: F,
+    check_sorted: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl<I: Iterator, F: FnMut(&I::Item, &I::Item) -> bool> KMergeIter<I, F> {
+    fn new(iters: impl IntoIterator<Item = impl IntoIterator<IntoIter = I>>, is_less: F) -> Self {
+        KMergeIter {
+            iters: iters.into_iter().map(|iter| iter.into_iter()).collect(),
+            heads: Vec::new(),
+            tree: None,
+            is_less,
+            check_sorted: false,
+        }
+    }
+
+    /// Panics when an input yields an element smaller than the previous one,
+    /// instead of silently producing an unsorted sequence.
+    pub fn check_sorted(mut self) -> Self {
+        self.check_sorted = true;
+        self
+    }
+
+    fn tree(&mut self) -> Option<&mut LoserTree> {
+        if self.tree.is_none() && !self.iters.is_empty() {
+            self.heads = self.iters.iter_mut().map(|iter| iter.next()).collect();
+            let (heads, is_less) = (&self.heads, &mut self.is_less);
+            self.tree = Some(LoserTree::new(heads.len(), |id1, id2| {
+                beats(heads[id1].as_ref(), id1, heads[id2].as_ref(), id2, is_less)
+            }));
+        }
+        self.tree.as_mut()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I: Iterator, F: FnMut(&I::Item, &I::Item) -> bool> Iterator for KMergeIter<I, F> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let winner = self.tree()?.winner();
+        let value = self.heads[winner].take()?;
+        self.heads[winner] = self.iters[winner].next();
+        let (heads, is_less) = (&self.heads, &mut self.is_less);
+        if self.check_sorted {
+            check_order(&value, heads[winner].as_ref(), winner, is_less);
+        }
+        if let Some(tree) = self.tree.as_mut() {
+            tree.replay(|id1, id2| {
+                beats(heads[id1].as_ref(), id1, heads[id2].as_ref(), id2, is_less)
+            });
+        }
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let heads = self.heads.iter().flatten().count();
+        add_size_hints(
+            self.iters
+                .iter()
+                .map(|iter| iter.size_hint())
+                .chain([(heads, Some(heads))]),
+        )
+    }
+}
+
+pub(super) fn check_order<T, F: FnMut(&T, &T) -> bool>(
+    value: &T,
+    next: Option<&T>,
+    id: usize,
+    is_less: &mut F,
+) {
+    if next.is_some_and(|next| is_less(next, value)) {
+        panic!("Input {id} of the merge is not sorted");
+    }
+}
+
+pub(super) fn add_size_hints(
+    hints: impl IntoIterator<Item = (usize, Option<usize>)>,
+) -> (usize, Option<usize>) {
+    hints
+        .into_iter()
+        .fold((0, Some(0)), |(lower, upper), (hint_lower, hint_upper)| {
+            (
+                lower.saturating_add(hint_lower),
+                upper.zip(hint_upper).and_then(|(a, b)| a.checked_add(b)),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_iter_test() {
+        let merged: Vec<u32> = merge_iter([1, 3, 5, 7], (2..5).chain(9..11)).collect();
+        assert_eq!(merged, vec![1, 2, 3, 3, 4, 5, 7, 9, 10]);
+        let merged = merge_iter_by([9, 4, 4, 1], vec![8, 4, 0], |x, y| y.cmp(x));
+        assert_eq!(merged.size_hint(), (7, Some(7)));
+        assert_eq!(merged.collect::<Vec<_>>(), vec![9, 8, 4, 4, 4, 1, 0]);
+        // Unbounded inputs are merged lazily
+        let evens = (0..).step_by(2);
+        let odds = (1..).step_by(2);
+        let merged: Vec<u32> = merge_iter(evens, odds).take(6).collect();
+        assert_eq!(merged, vec![0, 1, 2, 3, 4, 5]);
+        let merged: Vec<u32> = merge_iter([1, 1, 3, 5, 5], [1, 2, 5, 6, 6])
+            .dedup()
+            .collect();
+        assert_eq!(merged, vec![1, 2, 3, 5, 6]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn kmerge_iter_is_stable() {
+        for k in 0..12u32 {
+            let inputs: Vec<Vec<(u32, u32)>> = (0..k)
+                .map(|id| {
+                    (0..(id * 37) % 23)
+                        .map(|i| (i * (id + 3) / 7, id))
+                        .collect()
+                })
+                .collect();
+            let mut expected = inputs.concat();
+            expected.sort_by_key(|v| v.0);
+            let merged = kmerge_iter_by(inputs.clone(), |x, y| x.0.cmp(&y.0));
+            assert_eq!(merged.size_hint().0, expected.len());
+            assert_eq!(
+                merged.check_sorted().collect::<Vec<_>>(),
+                expected,
+                "{k} inputs"
+            );
+            expected.sort();
+            assert_eq!(
+                kmerge_iter(inputs).collect::<Vec<_>>(),
+                expected,
+                "{k} inputs"
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Input 1 of the merge is not sorted")]
+    fn merge_iter_check_sorted() {
+        merge_iter([1, 2, 3], [4, 0]).check_sorted().for_each(drop);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    #[should_panic(expected = "Input 2 of the merge is not sorted")]
+    fn kmerge_iter_check_sorted() {
+        kmerge_iter([vec![1, 2], vec![3], vec![0, 5, 4]])
+            .check_sorted()
+            .for_each(drop);
+    }
+}
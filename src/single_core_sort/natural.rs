@@ -0,0 +1,145 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use super::{SortTraits, SortVecPair, merge_bins};
+
+/// Merges the runs already present in the input instead of starting from
+/// single elements, sorted input is handled in a single linear scan.
+pub fn merge_sort_natural<T: SortTraits + Clone>(input: &[T]) -> Vec<T> {
+    let mut values = input.to_vec();
+    merge_sort_natural_in_place(&mut values);
+    values
+}
+
+pub fn merge_sort_natural_in_place<T: SortTraits>(values: &mut [T]) {
+    natural_merge_sort_impl(values, &mut |a: &T, b: &T| a < b);
+}
+
+pub fn merge_sort_natural_in_place_by<T, F>(values: &mut [T], mut compare: F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    natural_merge_sort_impl(values, &mut |a: &T, b: &T| compare(a, b) == Ordering::Less);
+}
+
+fn natural_merge_sort_impl<T, F: FnMut(&T, &T) -> bool>(values: &mut [T], is_less: &mut F) {
+    let mut run_ends = find_runs(values, is_less);
+    if run_ends.len() <= 1 {
+        // Already sorted, no need for a buffer
+        return;
+    }
+    let mut buffer = Box::new_uninit_slice(values.len());
+    let mut sort_vec_pair = SortVecPair::new(values, &mut buffer, 1);
+    while run_ends.len() > 1 {
+        // Merge the runs two by two, a lone last run is only moved
+        let mut merged_run_ends = Vec::with_capacity(run_ends.len().div_ceil(2));
+        let mut start = 0;
+        for pair in run_ends.chunks(2) {
+            let (mid, end) = match *pair {
+                [mid, end] => (mid, end),
+                [end] => (end, end),
+                _ => unreachable!(),
+            };
+            let (source, destination) = sort_vec_pair.get_source_and_destination();
+            let bin1 = &source[start..mid];
+            let bin2 = &source[mid..end];
+            let buf = &mut destination[start..end];
+            // SAFETY: every element of the source is copied once to the destination
+            // and the roles are swapped at the end of the pass
+            unsafe { merge_bins(bin1, bin2, buf, is_less) };
+            merged_run_ends.push(end);
+            start = end;
+        }
+        run_ends = merged_run_ends;
+        sort_vec_pair.finish_merge();
+    }
+    // Dropping the pair moves the values back from the buffer if needed
+}
+
+// Splits the values into ascending runs and returns the end of each run
+fn find_runs<T, F: FnMut(&T, &T) -> bool>(values: &mut [T], is_less: &mut F) -> Vec<usize> {
+    let mut run_ends = Vec::new();
+    let mut start = 0;
+    while start < values.len() {
+        let end = find_run(values, start, is_less);
+        run_ends.push(end);
+        start = end;
+    }
+    run_ends
+}
+
+// Returns the end of the ascending run beginning at `start`.
+// Strictly descending runs are reversed in place, which keeps the sort stable
+// since they cannot contain equal values.
+pub(super) fn find_run<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    start: usize,
+    is_less: &mut F,
+) -> usize {
+    let mut end = start + 1;
+    if end < values.len() {
+        let descending = is_less(&values[end], &values[start]);
+        end += 1;
+        while end < values.len() && is_less(&values[end], &values[end - 1]) == descending {
+            end += 1;
+        }
+        if descending {
+            values[start..end].reverse();
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn find_runs_test() {
+        let mut test_vec = vec![1, 2, 2, 5, 4, 3, 3, 7, 0];
+        let run_ends = find_runs(&mut test_vec, &mut |a: &i32, b: &i32| a < b);
+        assert_eq!(run_ends, vec![4, 6, 8, 9]);
+        assert_eq!(test_vec, vec![1, 2, 2, 5, 3, 4, 3, 7, 0]);
+    }
+
+    #[test]
+    fn sorted_input_is_a_single_scan() {
+        let mut test_vec: Vec<u32> = (0..1000).collect();
+        let mut comparisons = 0;
+        merge_sort_natural_in_place_by(&mut test_vec, |a, b| {
+            comparisons += 1;
+            a.cmp(b)
+        });
+        assert_eq!(comparisons, 999);
+        assert_eq!(test_vec, (0..1000).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn sort_reversed_and_nearly_sorted() {
+        let reversed: Vec<u32> = (0..100).rev().collect();
+        assert_eq!(
+            merge_sort_natural(&reversed),
+            (0..100).collect::<Vec<u32>>()
+        );
+        for length in 1..70 {
+            let mut test_vec: Vec<u32> = (0..length).collect();
+            test_vec.swap(length as usize / 3, length as usize / 2);
+            test_vec.extend(scrambled::<u32>(length as usize, 31));
+            let mut expected = test_vec.clone();
+            expected.sort();
+            assert_eq!(merge_sort_natural(&test_vec), expected, "length {length}");
+        }
+    }
+
+    #[test]
+    fn sort_natural_is_stable() {
+        let mut test_vec = vec![(3, 'a'), (2, 'b'), (2, 'c'), (1, 'd'), (2, 'e')];
+        merge_sort_natural_in_place_by(&mut test_vec, |a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            test_vec,
+            vec![(1, 'd'), (2, 'b'), (2, 'c'), (2, 'e'), (3, 'a')]
+        );
+    }
+}
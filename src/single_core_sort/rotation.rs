@@ -0,0 +1,194 @@
+use core::cmp::Ordering;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use super::{MergeHole, PassObserver, SortConfig, SortTraits, sort_blocks};
+
+/// Stable merge sort without any buffer, the merges exchange blocks of values
+/// with rotations instead (SymMerge). Never allocates, and is simpler than
+/// `block_merge_sort_in_place` on inputs with few distinct values, but makes
+/// O(n log² n) moves where the block merge sort keeps them at O(n log n).
+pub fn rotation_merge_sort_in_place<T: SortTraits>(values: &mut [T]) {
+    rotation_merge_sort(values, &mut |a: &T, b: &T| a < b);
+}
+
+pub fn rotation_merge_sort_in_place_by<T, F>(values: &mut [T], mut compare: F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    rotation_merge_sort(values, &mut |a: &T, b: &T| compare(a, b) == Ordering::Less);
+}
+
+fn rotation_merge_sort<T, F: FnMut(&T, &T) -> bool>(values: &mut [T], is_less: &mut F) {
+    let config = SortConfig::default();
+    sort_blocks(values, config, is_less, &mut ());
+    rotation_merge_sort_impl(values, &mut [], config.block_size(), is_less, &mut ());
+}
+
+// Bottom-up merge passes on blocks of `block_size` sorted values,
+// the scratch space may have any length
+pub(super) fn rotation_merge_sort_impl<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    scratch: &mut [MaybeUninit<T>],
+    block_size: usize,
+    is_less: &mut F,
+    observer: &mut impl PassObserver,
+) {
+    let length = values.len();
+    let mut bin_size = block_size;
+    while bin_size < length {
+        observer.start_pass();
+        for bins in values.chunks_mut(2 * bin_size) {
+            if bins.len() > bin_size {
+                merge_in_place(bins, bin_size, scratch, is_less);
+            }
+        }
+        bin_size *= 2;
+        observer.finish_pass();
+    }
+}
+
+// Stable merge of values[..mid] and values[mid..], SymMerge from Kim and Kutzner.
+// The middle of the merged sequence splits both sides, the parts that are on
+// the wrong side are exchanged with a rotation and each half is merged recursively.
+// Once the left side fits in the scratch space it is merged like `merge_bins`.
+pub(super) fn merge_in_place<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    mid: usize,
+    scratch: &mut [MaybeUninit<T>],
+    is_less: &mut F,
+) {
+    let length = values.len();
+    if mid == 0 || mid == length || !is_less(&values[mid], &values[mid - 1]) {
+        return;
+    }
+    if mid <= scratch.len() {
+        // SAFETY: the scratch space holds at least the left side
+        unsafe { merge_with_scratch(values, mid, scratch, true, is_less) };
+        return;
+    }
+    if mid == 1 {
+        // Insert the only left value after the smaller right values
+        let position = values[1..].partition_point(|v| is_less(v, &values[0]));
+        values[..=position].rotate_left(1);
+        return;
+    }
+    if length - mid == 1 {
+        // Insert the only right value after the left values not greater than it
+        let position = values[..mid].partition_point(|v| !is_less(&values[mid], v));
+        values[position..].rotate_right(1);
+        return;
+    }
+
+    let half = length / 2;
+    let n = half + mid;
+    // Binary search of the split such that values[split..mid] and values[mid..n - split]
+    // are exchanged
+    let (mut start, mut end) = if mid > half {
+        (n - length, half)
+    } else {
+        (0, mid)
+    };
+    while start < end {
+        let c = (start + end) / 2;
+        if !is_less(&values[n - 1 - c], &values[c]) {
+            start = c + 1;
+        } else {
+            end = c;
+        }
+    }
+    let split = start;
+    let split_end = n - split;
+    values[split..split_end].rotate_left(mid - split);
+    let (left, right) = values.split_at_mut(half);
+    merge_in_place(left, split, scratch, is_less);
+    merge_in_place(right, split_end - half, scratch, is_less);
+}
+
+// Stops once one side is used up and returns where the values left of the other
+// side start, and whether they come from the left side. Equal values are taken from
+// the left side first unless `left_wins_ties` is false.
+// SAFETY: the scratch space must hold at least `mid` values
+pub(super) unsafe fn merge_with_scratch<T, F: FnMut(&T, &T) -> bool>(
+    values: &mut [T],
+    mid: usize,
+    scratch: &mut [MaybeUninit<T>],
+    left_wins_ties: bool,
+    is_less: &mut F,
+) -> (usize, bool) {
+    let length = values.len();
+    let values = values.as_mut_ptr();
+    // SAFETY: as in `TimSort::merge_low`, the hole moves the rest of the left side
+    // back to the values when dropped, including when a comparison panics
+    unsafe {
+        let buffer = scratch.as_mut_ptr() as *mut T;
+        ptr::copy_nonoverlapping(values, buffer, mid);
+        let mut hole = MergeHole {
+            buffer,
+            start: 0,
+            end: mid,
+            dest: values,
+        };
+        let mut id2 = mid;
+        while hole.start < hole.end && id2 < length {
+            let (right, left) = (&*values.add(id2), &*buffer.add(hole.start));
+            let take_right = if left_wins_ties {
+                is_less(right, left)
+            } else {
+                !is_less(left, right)
+            };
+            if take_right {
+                hole.take_right(values.add(id2), 1);
+                id2 += 1;
+            } else {
+                hole.take_left(1);
+            }
+        }
+        if hole.start < hole.end {
+            (length - (hole.end - hole.start), true)
+        } else {
+            (id2, false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn merge_in_place_test() {
+        // Every split of a few sorted sequences, with and without scratch space
+        let test_vec: Vec<(u32, u32)> = scrambled(40, 11).zip(0..).collect();
+        for mid in 0..=test_vec.len() {
+            let mut expected = test_vec.clone();
+            expected[..mid].sort_by_key(|v| v.0);
+            expected[mid..].sort_by_key(|v| v.0);
+            let input = expected.clone();
+            expected.sort_by_key(|v| v.0);
+            for scratch_length in [0, 3, 40] {
+                let mut values = input.clone();
+                let mut scratch = Box::new_uninit_slice(scratch_length);
+                merge_in_place(&mut values, mid, &mut scratch, &mut |a, b| a.0 < b.0);
+                assert_eq!(values, expected, "mid {mid}, scratch {scratch_length}");
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_merge_sort_is_stable() {
+        for length in [0, 1, 2, 17, 100, 1000, 4099] {
+            let test_vec: Vec<(u32, u32)> = scrambled(length, 13).zip(0..).collect();
+            let mut expected = test_vec.clone();
+            expected.sort_by_key(|v| v.0);
+            let mut values = test_vec.clone();
+            rotation_merge_sort_in_place_by(&mut values, |a, b| a.0.cmp(&b.0));
+            assert_eq!(values, expected, "length {length}");
+
+            let mut values: Vec<u32> = test_vec.iter().map(|v| v.0).collect();
+            rotation_merge_sort_in_place(&mut values);
+            assert!(values.is_sorted());
+        }
+    }
+}
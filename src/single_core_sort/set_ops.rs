@@ -0,0 +1,356 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter::Peekable;
+
+use super::SortTraits;
+use super::merge_iter::{add_size_hints, check_order};
+
+/// Elements of two sorted sequences kept by a set operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetOp {
+    Union,
+    Intersection,
+    /// Elements of the first sequence missing from the second one.
+    Difference,
+    SymmetricDifference,
+}
+
+/// How the operations count equal elements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Semantics {
+    /// Each distinct element appears at most once in the output, the first one is kept.
+    Set,
+    /// An element appearing `m` times in the first sequence and `n` times in the second
+    /// appears `max(m, n)` times in the union, `min(m, n)` times in the intersection,
+    /// `m - n` times in the difference and `|m - n|` times in the symmetric difference.
+    Multiset,
+}
+
+impl SetOp {
+    fn keeps_first_only(self) -> bool {
+        matches!(
+            self,
+            SetOp::Union | SetOp::Difference | SetOp::SymmetricDifference
+        )
+    }
+
+    fn keeps_second_only(self) -> bool {
+        matches!(self, SetOp::Union | SetOp::SymmetricDifference)
+    }
+
+    fn keeps_both(self) -> bool {
+        matches!(self, SetOp::Union | SetOp::Intersection)
+    }
+}
+
+/// Set operation on two sorted slices, equal elements come from `a` when both
+/// hold them.
+#[cfg(feature = "alloc")]
+pub fn set_op<T: SortTraits + Clone>(a: &[T], b: &[T], op: SetOp, semantics: Semantics) -> Vec<T> {
+    set_op_impl(a, b, op, semantics, &mut |x: &T, y: &T| x < y)
+}
+
+#[cfg(feature = "alloc")]
+pub fn set_op_by<T, F>(a: &[T], b: &[T], op: SetOp, semantics: Semantics, mut compare: F) -> Vec<T>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    set_op_impl(a, b, op, semantics, &mut |x: &T, y: &T| {
+        compare(x, y) == Ordering::Less
+    })
+}
+
+/// Lazy set operation on two sorted iterators, see `set_op`.
+pub fn set_op_iter<T, A, B>(
+    a: A,
+    b: B,
+    op: SetOp,
+    semantics: Semantics,
+) -> SetOpIter<A::IntoIter, B::IntoIter, impl FnMut(&T, &T) -> bool>
+where
+    T: SortTraits,
+    A: IntoIterator<Item = T>,
+    B: IntoIterator<Item = T>,
+{
+    SetOpIter::new(
+        a.into_iter(),
+        b.into_iter(),
+        op,
+        semantics,
+        |x: &T, y: &T| x < y,
+    )
+}
+
+pub fn set_op_iter_by<T, A, B, F>(
+    a: A,
+    b: B,
+    op: SetOp,
+    semantics: Semantics,
+    mut compare: F,
+) -> SetOpIter<A::IntoIter, B::IntoIter, impl FnMut(&T, &T) -> bool>
+where
+    A: IntoIterator<Item = T>,
+    B: IntoIterator<Item = T>,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    SetOpIter::new(
+        a.into_iter(),
+        b.into_iter(),
+        op,
+        semantics,
+        move |x: &T, y: &T| compare(x, y) == Ordering::Less,
+    )
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn set_op_impl<T: Clone, F: FnMut(&T, &T) -> bool>(
+    a: &[T],
+    b: &[T],
+    op: SetOp,
+    semantics: Semantics,
+    is_less: &mut F,
+) -> Vec<T> {
+    SetOpIter::new(a.iter(), b.iter(), op, semantics, |x: &&T, y: &&T| {
+        is_less(x, y)
+    })
+    .cloned()
+    .collect()
+}
+
+/// Set operation on sorted iterators, see `set_op_iter`.
+pub struct SetOpIter<A: Iterator, B: Iterator<Item = A::Item>, F> {
+    a: Peekable<A>,
+    b: Peekable<B>,
+    op: SetOp,
+    semantics: Semantics,
+    is_less: F,
+    check_sorted: bool,
+}
+
+impl<A, B, F> SetOpIter<A, B, F>
+where
+    A: Iterator,
+    B: Iterator<Item = A::Item>,
+    F: FnMut(&A::Item, &A::Item) -> bool,
+{
+    fn new(a: A, b: B, op: SetOp, semantics: Semantics, is_less: F) -> Self {
+        SetOpIter {
+            a: a.peekable(),
+            b: b.peekable(),
+            op,
+            semantics,
+            is_less,
+            check_sorted: false,
+        }
+    }
+
+    /// Panics when an input yields an element smaller than the previous one,
+    /// instead of silently producing a wrong result.
+    pub fn check_sorted(mut self) -> Self {
+        self.check_sorted = true;
+        self
+    }
+}
+
+impl<A, B, F> Iterator for SetOpIter<A, B, F>
+where
+    A: Iterator,
+    B: Iterator<Item = A::Item>,
+    F: FnMut(&A::Item, &A::Item) -> bool,
+{
+    type Item = A::Item;
+
+    fn next(&mut self) -> Option<A::Item> {
+        let SetOpIter {
+            a,
+            b,
+            op,
+            semantics,
+            is_less,
+            check_sorted,
+        } = self;
+        let dedup = *semantics == Semantics::Set;
+        loop {
+            let ordering = match (a.peek(), b.peek()) {
+                (Some(head_a), Some(head_b)) => {
+                    if is_less(head_a, head_b) {
+                        Ordering::Less
+                    } else if is_less(head_b, head_a) {
+                        Ordering::Greater
+                    } else {
+                        Ordering::Equal
+                    }
+                }
+                // Nothing left that the operation keeps
+                (Some(_), None) if !op.keeps_first_only() => return None,
+                (None, Some(_)) if !op.keeps_second_only() => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return None,
+            };
+            match ordering {
+                Ordering::Less => {
+                    let value = take(a, 0, dedup, *check_sorted, is_less);
+                    if op.keeps_first_only() {
+                        return Some(value);
+                    }
+                }
+                Ordering::Greater => {
+                    let value = take(b, 1, dedup, *check_sorted, is_less);
+                    if op.keeps_second_only() {
+                        return Some(value);
+                    }
+                }
+                // Equal elements are paired one by one
+                Ordering::Equal => {
+                    let value = take(a, 0, dedup, *check_sorted, is_less);
+                    take(b, 1, dedup, *check_sorted, is_less);
+                    if op.keeps_both() {
+                        return Some(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, upper_a) = self.a.size_hint();
+        let (_, upper_b) = self.b.size_hint();
+        let upper = match self.op {
+            SetOp::Union | SetOp::SymmetricDifference => {
+                add_size_hints([self.a.size_hint(), self.b.size_hint()]).1
+            }
+            SetOp::Intersection => match (upper_a, upper_b) {
+                (Some(upper_a), Some(upper_b)) => Some(upper_a.min(upper_b)),
+                (upper_a, upper_b) => upper_a.or(upper_b),
+            },
+            SetOp::Difference => upper_a,
+        };
+        (0, upper)
+    }
+}
+
+// Next element of the input, along with the following equal ones in set semantics
+fn take<I: Iterator, F: FnMut(&I::Item, &I::Item) -> bool>(
+    input: &mut Peekable<I>,
+    id: usize,
+    dedup: bool,
+    check_sorted: bool,
+    is_less: &mut F,
+) -> I::Item {
+    let value = input.next().unwrap();
+    if dedup {
+        while input
+            .next_if(|next| !is_less(&value, next) && !is_less(next, &value))
+            .is_some()
+        {}
+    }
+    if check_sorted {
+        check_order(&value, input.peek(), id, is_less);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    const OPS: [SetOp; 4] = [
+        SetOp::Union,
+        SetOp::Intersection,
+        SetOp::Difference,
+        SetOp::SymmetricDifference,
+    ];
+
+    // Count of a value in the output, from its counts in the inputs
+    fn expected_count(op: SetOp, semantics: Semantics, m: usize, n: usize) -> usize {
+        let (m, n) = match semantics {
+            Semantics::Set => (m.min(1), n.min(1)),
+            Semantics::Multiset => (m, n),
+        };
+        match op {
+            SetOp::Union => m.max(n),
+            SetOp::Intersection => m.min(n),
+            SetOp::Difference => m.saturating_sub(n),
+            SetOp::SymmetricDifference => m.abs_diff(n),
+        }
+    }
+
+    #[test]
+    fn set_op_counts() {
+        let mut a: Vec<u32> = scrambled(60, 13).collect();
+        let mut b: Vec<u32> = (0..45).map(|i| (i * 104729) % 17).collect();
+        a.sort();
+        b.sort();
+        for op in OPS {
+            for semantics in [Semantics::Set, Semantics::Multiset] {
+                let result: Vec<u32> =
+                    set_op_iter(a.iter().copied(), b.iter().copied(), op, semantics)
+                        .check_sorted()
+                        .collect();
+                for value in 0..17 {
+                    let count = |v: &[u32]| v.iter().filter(|x| **x == value).count();
+                    assert_eq!(
+                        count(&result),
+                        expected_count(op, semantics, count(&a), count(&b)),
+                        "{op:?} {semantics:?} of {value}"
+                    );
+                }
+                #[cfg(feature = "alloc")]
+                assert_eq!(set_op(&a, &b, op, semantics), result);
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn set_op_keeps_elements_of_the_first_input() {
+        let a = [(1, 'a'), (2, 'a'), (2, 'b'), (4, 'a')];
+        let b = [(2, 'c'), (3, 'c'), (4, 'c'), (4, 'd')];
+        let compare = |x: &(u32, char), y: &(u32, char)| x.0.cmp(&y.0);
+        assert_eq!(
+            set_op_by(&a, &b, SetOp::Union, Semantics::Multiset, compare),
+            [(1, 'a'), (2, 'a'), (2, 'b'), (3, 'c'), (4, 'a'), (4, 'd')]
+        );
+        assert_eq!(
+            set_op_by(&a, &b, SetOp::Union, Semantics::Set, compare),
+            [(1, 'a'), (2, 'a'), (3, 'c'), (4, 'a')]
+        );
+        assert_eq!(
+            set_op_by(&a, &b, SetOp::Intersection, Semantics::Set, compare),
+            [(2, 'a'), (4, 'a')]
+        );
+    }
+
+    #[test]
+    fn set_op_iter_by_comparator() {
+        let a = [(1, 'a'), (2, 'a'), (2, 'b'), (4, 'a')];
+        let b = [(2, 'c'), (3, 'c'), (4, 'c'), (4, 'd')];
+        let intersection: Vec<(u32, char)> =
+            set_op_iter_by(a, b, SetOp::Intersection, Semantics::Multiset, |x, y| {
+                x.0.cmp(&y.0)
+            })
+            .collect();
+        assert_eq!(intersection, [(2, 'a'), (4, 'a')]);
+        let descending: Vec<u32> = set_op_iter_by(
+            [9, 7, 7, 3],
+            [8, 7, 3, 1],
+            SetOp::Difference,
+            Semantics::Multiset,
+            |x, y| y.cmp(x),
+        )
+        .collect();
+        assert_eq!(descending, [9, 7]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Input 1 of the merge is not sorted")]
+    fn set_op_iter_checks_order() {
+        set_op_iter([1, 2], [3, 1], SetOp::Union, Semantics::Multiset)
+            .check_sorted()
+            .for_each(drop);
+    }
+}
@@ -0,0 +1,152 @@
+// Batcher odd-even merge sort networks, pairs of positions to compare and exchange
+#[rustfmt::skip]
+const NETWORK_4: [(usize, usize); 5] = [(0, 1), (2, 3), (0, 2), (1, 3), (1, 2)];
+#[rustfmt::skip]
+const NETWORK_8: [(usize, usize); 19] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), (0, 2), (1, 3), (4, 6), (5, 7), (1, 2), (5, 6),
+    (0, 4), (1, 5), (2, 6), (3, 7), (2, 4), (3, 5), (1, 2), (3, 4), (5, 6),
+];
+#[rustfmt::skip]
+const NETWORK_16: [(usize, usize); 63] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), (8, 9), (10, 11), (12, 13), (14, 15),
+    (0, 2), (1, 3), (4, 6), (5, 7), (8, 10), (9, 11), (12, 14), (13, 15),
+    (1, 2), (5, 6), (9, 10), (13, 14),
+    (0, 4), (1, 5), (2, 6), (3, 7), (8, 12), (9, 13), (10, 14), (11, 15),
+    (2, 4), (3, 5), (10, 12), (11, 13),
+    (1, 2), (3, 4), (5, 6), (9, 10), (11, 12), (13, 14),
+    (0, 8), (1, 9), (2, 10), (3, 11), (4, 12), (5, 13), (6, 14), (7, 15),
+    (4, 8), (5, 9), (6, 10), (7, 11),
+    (2, 4), (3, 5), (6, 8), (7, 9), (10, 12), (11, 13),
+    (1, 2), (3, 4), (5, 6), (7, 8), (9, 10), (11, 12), (13, 14),
+];
+
+/// Sort used on the small blocks before the merge passes begin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmallSort {
+    /// Stable, works for any block size.
+    Insertion,
+    /// Fixed sorting networks for blocks of 4, 8 or 16 elements,
+    /// the threshold is rounded down to one of these sizes.
+    /// Not stable: equal elements may be reordered.
+    Network,
+}
+
+/// Extra memory used by the merges of the sequential sorts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scratch {
+    /// A buffer as large as the input, the fastest.
+    Full,
+    /// A buffer of about the square root of the input length, the runs are
+    /// merged block by block through it (block merge sort): O(n log n) moves.
+    /// The blocks are tagged with about 2√n distinct values of the input,
+    /// with fewer the merges split the runs with rotations: O(n log² n) moves.
+    Sqrt,
+    /// No buffer, about 3√n distinct values of the input tag the blocks and
+    /// serve as the merge buffer: O(n log n) moves. With fewer distinct values
+    /// the merges only use rotations: O(n log² n) moves.
+    Constant,
+}
+
+/// Tuning of the merge sorts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortConfig {
+    pub small_sort: SmallSort,
+    /// Size of the blocks sorted before merging, 0 or 1 merges from single elements.
+    pub small_sort_threshold: usize,
+    /// Only used by the sequential sorts, the parallel sorts always use a full buffer.
+    pub scratch: Scratch,
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        SortConfig {
+            small_sort: SmallSort::Insertion,
+            small_sort_threshold: 16,
+            scratch: Scratch::Full,
+        }
+    }
+}
+
+impl SortConfig {
+    // Size of the sorted bins the merge passes start from
+    pub(crate) fn block_size(&self) -> usize {
+        match self.small_sort {
+            SmallSort::Insertion => self.small_sort_threshold.max(1),
+            SmallSort::Network => [16, 8, 4]
+                .into_iter()
+                .find(|size| *size <= self.small_sort_threshold)
+                .unwrap_or(1),
+        }
+    }
+
+    // Sorts a block of at most `block_size` values
+    pub(crate) fn sort_block<T, F: FnMut(&T, &T) -> bool>(&self, block: &mut [T], is_less: &mut F) {
+        match (self.small_sort, block.len()) {
+            (SmallSort::Network, 4) => sort_network(block, &NETWORK_4, is_less),
+            (SmallSort::Network, 8) => sort_network(block, &NETWORK_8, is_less),
+            (SmallSort::Network, 16) => sort_network(block, &NETWORK_16, is_less),
+            // Also used for the shorter last block
+            _ => insertion_sort(block, is_less),
+        }
+    }
+}
+
+pub(super) fn insertion_sort<T, F: FnMut(&T, &T) -> bool>(block: &mut [T], is_less: &mut F) {
+    for i in 1..block.len() {
+        // Stop at equal values to keep the sort stable
+        let mut j = i;
+        while j > 0 && is_less(&block[j], &block[j - 1]) {
+            block.swap(j, j - 1);
+            j -= 1;
+        }
+    }
+}
+
+fn sort_network<T, F: FnMut(&T, &T) -> bool>(
+    block: &mut [T],
+    network: &[(usize, usize)],
+    is_less: &mut F,
+) {
+    for &(i, j) in network {
+        if is_less(&block[j], &block[i]) {
+            block.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks_sort_all_binary_inputs() {
+        // Zero-one principle: a network sorting every sequence of 0 and 1 sorts everything
+        for network in [&NETWORK_4[..], &NETWORK_8[..], &NETWORK_16[..]] {
+            let size = network.iter().map(|&(_, j)| j).max().unwrap() + 1;
+            for bits in 0..1u32 << size {
+                let mut block: Vec<u32> = (0..size).map(|i| (bits >> i) & 1).collect();
+                sort_network(&mut block, network, &mut |a: &u32, b: &u32| a < b);
+                assert!(
+                    block.is_sorted(),
+                    "network of size {size} fails on {bits:b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn block_size_test() {
+        let mut config = SortConfig {
+            small_sort: SmallSort::Network,
+            small_sort_threshold: 12,
+            ..SortConfig::default()
+        };
+        assert_eq!(config.block_size(), 8);
+        config.small_sort_threshold = 3;
+        assert_eq!(config.block_size(), 1);
+        config.small_sort = SmallSort::Insertion;
+        assert_eq!(config.block_size(), 3);
+        config.small_sort_threshold = 0;
+        assert_eq!(config.block_size(), 1);
+    }
+}
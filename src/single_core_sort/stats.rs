@@ -0,0 +1,83 @@
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// What a sort did, returned by the `_with_stats` variants of the sorts.
+/// The other variants do not collect anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortStats {
+    /// Calls to the comparison, 0 on the GPU where they are not counted.
+    pub comparisons: u64,
+    /// Elements moved between the values and the buffer by the merges, the moves
+    /// made by the sort of the small blocks and by the rotations are not counted.
+    pub moves: u64,
+    /// Passes over the data: the sort of the small blocks, then each merge pass.
+    pub passes: usize,
+    /// Threads spawned or tasks sent to the thread pool, workgroups on the GPU.
+    pub dispatches: usize,
+    /// Buffers of elements allocated, the copy of the input included.
+    pub allocations: usize,
+    /// Duration of each pass, on the GPU each pass is waited for to time it.
+    #[cfg(feature = "std")]
+    pub pass_times: Vec<Duration>,
+}
+
+// Hooks of the sorts that can report statistics. The sorts without statistics
+// use `()`, whose hooks do nothing and are optimized out.
+// Some hooks are only called by the parallel and GPU sorts.
+#[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+pub(crate) trait PassObserver {
+    // Whether the GPU has to wait for each pass before starting the next one
+    const TIMES_PASSES: bool = false;
+
+    fn start_pass(&mut self) {}
+
+    fn finish_pass(&mut self) {}
+
+    fn moved(&mut self, _count: usize) {}
+
+    fn dispatched(&mut self, _count: usize) {}
+
+    fn allocated(&mut self, _count: usize) {}
+}
+
+impl PassObserver for () {}
+
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+pub(crate) struct StatsRecorder {
+    pub(crate) stats: SortStats,
+    #[cfg(feature = "std")]
+    pass_start: Option<Instant>,
+}
+
+#[cfg(feature = "alloc")]
+impl PassObserver for StatsRecorder {
+    const TIMES_PASSES: bool = cfg!(feature = "std");
+
+    fn start_pass(&mut self) {
+        #[cfg(feature = "std")]
+        {
+            self.pass_start = Some(Instant::now());
+        }
+    }
+
+    fn finish_pass(&mut self) {
+        self.stats.passes += 1;
+        #[cfg(feature = "std")]
+        if let Some(start) = self.pass_start.take() {
+            self.stats.pass_times.push(start.elapsed());
+        }
+    }
+
+    fn moved(&mut self, count: usize) {
+        self.stats.moves += count as u64;
+    }
+
+    fn dispatched(&mut self, count: usize) {
+        self.stats.dispatches += count;
+    }
+
+    fn allocated(&mut self, count: usize) {
+        self.stats.allocations += count;
+    }
+}
@@ -0,0 +1,308 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ptr;
+use core::slice;
+
+use super::natural::find_run;
+use super::{MergeHole, SortTraits};
+
+// Runs shorter than this are extended with an insertion sort
+const MAX_MIN_RUN: usize = 64;
+// Number of consecutive wins of one side before switching to galloping
+const MIN_GALLOP: usize = 7;
+
+/// Adaptive merge sort in the style of TimSort: natural runs are extended to
+/// a minimum length, kept on a stack and merged with galloping merges.
+pub fn timsort<T: SortTraits + Clone>(input: &[T]) -> Vec<T> {
+    let mut values = input.to_vec();
+    timsort_in_place(&mut values);
+    values
+}
+
+pub fn timsort_in_place<T: SortTraits>(values: &mut [T]) {
+    timsort_impl(values, &mut |a: &T, b: &T| a < b);
+}
+
+pub fn timsort_in_place_by<T, F>(values: &mut [T], mut compare: F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    timsort_impl(values, &mut |a: &T, b: &T| compare(a, b) == Ordering::Less);
+}
+
+#[derive(Clone, Copy)]
+struct Run {
+    start: usize,
+    length: usize,
+}
+
+struct TimSort<'a, T, F: FnMut(&T, &T) -> bool> {
+    values: &'a mut [T],
+    is_less: &'a mut F,
+    runs: Vec<Run>,
+    // Bitwise copy of the left run during a merge, its length stays 0
+    // so that the copied values are never dropped from there
+    buffer: Vec<T>,
+    // Adapts to the data, lower when galloping pays off
+    min_gallop: usize,
+}
+
+pub(crate) fn timsort_impl<T, F: FnMut(&T, &T) -> bool>(values: &mut [T], is_less: &mut F) {
+    let length = values.len();
+    let min_run = compute_min_run(length);
+    let mut tim_sort = TimSort {
+        values,
+        is_less,
+        runs: Vec::new(),
+        buffer: Vec::new(),
+        min_gallop: MIN_GALLOP,
+    };
+    let mut start = 0;
+    while start < length {
+        let mut end = find_run(tim_sort.values, start, tim_sort.is_less);
+        if end - start < min_run {
+            let extended_end = length.min(start + min_run);
+            tim_sort.insertion_sort(start, end, extended_end);
+            end = extended_end;
+        }
+        tim_sort.runs.push(Run {
+            start,
+            length: end - start,
+        });
+        tim_sort.merge_collapse();
+        start = end;
+    }
+    tim_sort.merge_force_collapse();
+}
+
+// Chooses a minimum run length in 32..=64 such that the number of runs is
+// a power of two or slightly less, which keeps the final merges balanced
+fn compute_min_run(mut length: usize) -> usize {
+    let mut remainder = 0;
+    while length >= MAX_MIN_RUN {
+        remainder |= length & 1;
+        length >>= 1;
+    }
+    length + remainder
+}
+
+// Number of leading elements of the slice for which the predicate holds,
+// the predicate must hold on a prefix of the slice only.
+// Exponential search first, then binary search in the last interval.
+fn gallop<T>(slice: &[T], mut pred: impl FnMut(&T) -> bool) -> usize {
+    let mut last = 0;
+    let mut offset = 1;
+    while offset <= slice.len() && pred(&slice[offset - 1]) {
+        last = offset;
+        offset *= 2;
+    }
+    let upper = if offset <= slice.len() {
+        offset - 1
+    } else {
+        slice.len()
+    };
+    last + slice[last..upper].partition_point(pred)
+}
+
+impl<T, F: FnMut(&T, &T) -> bool> TimSort<'_, T, F> {
+    // Binary insertion sort of values[start..end], values[start..sorted_end] being sorted
+    fn insertion_sort(&mut self, start: usize, sorted_end: usize, end: usize) {
+        for i in sorted_end..end {
+            // Insert after the equal values to keep the sort stable
+            let position = start
+                + self.values[start..i].partition_point(|v| !(self.is_less)(&self.values[i], v));
+            self.values[position..=i].rotate_right(1);
+        }
+    }
+
+    // Merges the runs on top of the stack until the lengths, from the top,
+    // grow faster than the Fibonacci sequence. This bounds the stack size
+    // and keeps merges between runs of similar lengths.
+    fn merge_collapse(&mut self) {
+        while self.runs.len() > 1 {
+            let mut n = self.runs.len() - 2;
+            let len = |id: usize| self.runs[id].length;
+            if (n > 0 && len(n - 1) <= len(n) + len(n + 1))
+                || (n > 1 && len(n - 2) <= len(n - 1) + len(n))
+            {
+                if len(n - 1) < len(n + 1) {
+                    n -= 1;
+                }
+                self.merge_at(n);
+            } else if len(n) <= len(n + 1) {
+                self.merge_at(n);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn merge_force_collapse(&mut self) {
+        while self.runs.len() > 1 {
+            let mut n = self.runs.len() - 2;
+            if n > 0 && self.runs[n - 1].length < self.runs[n + 1].length {
+                n -= 1;
+            }
+            self.merge_at(n);
+        }
+    }
+
+    // Merges the runs n and n + 1 of the stack
+    fn merge_at(&mut self, n: usize) {
+        let Run { start, length } = self.runs[n];
+        let right_length = self.runs[n + 1].length;
+        self.runs[n].length += right_length;
+        self.runs.remove(n + 1);
+
+        let mid = start + length;
+        let end = mid + right_length;
+        let (left, right) = self.values[start..end].split_at(length);
+        // Values of the left run not greater than the first value of the right run
+        // are already in place
+        let skipped = gallop(left, |v| !(self.is_less)(&right[0], v));
+        if skipped == length {
+            return;
+        }
+        // Same for values of the right run not smaller than the last value of the left run
+        let right_length = gallop(right, |v| (self.is_less)(v, &left[length - 1]));
+        self.merge_low(start + skipped, mid, mid + right_length);
+    }
+
+    // Merges values[start..mid] and values[mid..end], front to back.
+    // The left run is moved into the buffer, then both sides are merged into
+    // the values, taking from the right run only when strictly smaller like `merge_bins`.
+    fn merge_low(&mut self, start: usize, mid: usize, end: usize) {
+        let left_length = mid - start;
+        self.buffer.reserve(left_length);
+        let is_less = &mut *self.is_less;
+        let values = self.values.as_mut_ptr();
+        // SAFETY: the values between `hole.dest` and `id2` are always as many as the
+        // values left in the buffer, the hole fills them back when dropped,
+        // including when a comparison panics
+        unsafe {
+            let buffer = self.buffer.as_mut_ptr();
+            ptr::copy_nonoverlapping(values.add(start), buffer, left_length);
+            let mut hole = MergeHole {
+                buffer,
+                start: 0,
+                end: left_length,
+                dest: values.add(start),
+            };
+            let mut id2 = mid;
+
+            'merge: loop {
+                // One value at a time, until one side keeps winning
+                let mut wins1 = 0;
+                let mut wins2 = 0;
+                while wins1 < self.min_gallop && wins2 < self.min_gallop {
+                    if is_less(&*values.add(id2), &*buffer.add(hole.start)) {
+                        hole.take_right(values.add(id2), 1);
+                        id2 += 1;
+                        wins1 = 0;
+                        wins2 += 1;
+                    } else {
+                        hole.take_left(1);
+                        wins1 += 1;
+                        wins2 = 0;
+                    }
+                    if hole.start == hole.end || id2 == end {
+                        break 'merge;
+                    }
+                }
+
+                // Galloping, search for the length of the next winning streak
+                loop {
+                    let left = slice::from_raw_parts(buffer.add(hole.start), hole.end - hole.start);
+                    let count1 = gallop(left, |v| !is_less(&*values.add(id2), v));
+                    hole.take_left(count1);
+                    if hole.start == hole.end {
+                        break 'merge;
+                    }
+                    hole.take_right(values.add(id2), 1);
+                    id2 += 1;
+                    if id2 == end {
+                        break 'merge;
+                    }
+
+                    let right = slice::from_raw_parts(values.add(id2), end - id2);
+                    let count2 = gallop(right, |v| is_less(v, &*buffer.add(hole.start)));
+                    hole.take_right(values.add(id2), count2);
+                    id2 += count2;
+                    if id2 == end {
+                        break 'merge;
+                    }
+                    hole.take_left(1);
+                    if hole.start == hole.end {
+                        break 'merge;
+                    }
+
+                    self.min_gallop = self.min_gallop.saturating_sub(1).max(1);
+                    if count1 < MIN_GALLOP && count2 < MIN_GALLOP {
+                        break;
+                    }
+                }
+                // Penalize leaving the galloping mode
+                self.min_gallop += 2;
+            }
+            // The rest of the right run is already in place,
+            // dropping the hole moves the rest of the left run
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scrambled;
+
+    #[test]
+    fn compute_min_run_test() {
+        assert_eq!(compute_min_run(63), 63);
+        assert_eq!(compute_min_run(64), 32);
+        assert_eq!(compute_min_run(65), 33);
+        assert_eq!(compute_min_run(1 << 20), 32);
+    }
+
+    #[test]
+    fn gallop_test() {
+        let test_vec = [1, 2, 2, 3, 5, 8, 13, 21, 34];
+        for key in 0..40 {
+            assert_eq!(
+                gallop(&test_vec, |v| *v < key),
+                test_vec.partition_point(|v| *v < key)
+            );
+        }
+    }
+
+    #[test]
+    fn sort_structured_inputs() {
+        let length = 5000;
+        let sawtooth: Vec<u32> = (0..length).map(|i| i % 300).collect();
+        let organ_pipe: Vec<u32> = (0..length).map(|i| i.min(length - i)).collect();
+        let random: Vec<u32> = scrambled(length as usize, 1009).collect();
+        let mut runs_of_equals: Vec<u32> = (0..length).map(|i| i / 100).collect();
+        runs_of_equals.reverse();
+        for test_vec in [sawtooth, organ_pipe, random, runs_of_equals] {
+            let mut expected = test_vec.clone();
+            expected.sort();
+            assert_eq!(timsort(&test_vec), expected);
+        }
+        for length in 0..200 {
+            let test_vec: Vec<u32> = scrambled(length, 31).collect();
+            let mut expected = test_vec.clone();
+            expected.sort();
+            assert_eq!(timsort(&test_vec), expected, "length {length}");
+        }
+    }
+
+    #[test]
+    fn timsort_is_stable() {
+        // Few distinct keys, long enough to trigger galloping
+        let mut test_vec: Vec<(u32, u32)> = scrambled(3000, 7).zip(0..).collect();
+        test_vec.extend((0..3000).map(|i| (i / 500, i)));
+        let mut expected = test_vec.clone();
+        expected.sort_by_key(|v| v.0);
+        timsort_in_place_by(&mut test_vec, |a, b| a.0.cmp(&b.0));
+        assert_eq!(test_vec, expected);
+    }
+}
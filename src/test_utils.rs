@@ -0,0 +1,8 @@
+// Fixtures shared by the tests of the sorts.
+
+/// `length` values below `modulo`, scrambled by a multiplication with a prime so
+/// that every run of the tests sorts the same input. A small `modulo` gives many
+/// equal values, which is what the stability tests need.
+pub(crate) fn scrambled<T: From<u16>>(length: usize, modulo: u16) -> impl Iterator<Item = T> {
+    (0..length).map(move |i| T::from(((i * 7919) % usize::from(modulo)) as u16))
+}